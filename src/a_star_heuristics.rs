@@ -1,23 +1,102 @@
-use std::collections::HashMap;
-use rand::{thread_rng, Rng};
+use std::collections::{ BinaryHeap, HashMap, HashSet };
+use std::f64::consts::PI;
+use rand::{ thread_rng, Rng, SeedableRng, StdRng };
 
 use weighted_graph::{ GraphKey, Graph, Node };
-use road_weights::road_weight;
+use road_weights::{ ROAD_TYPE_SPEED, haversine };
 use dijkstra::shortest_path;
-use pathfinder::HeuristicFn;
+use pathfinder::{ CurrentBest, HeuristicFn };
+use contraction_hierarchy::ContractionHierarchy;
+use phast::Phast;
+
+// x and y are lng/lat in degrees, or plain coordinates measured in the same unit as each other
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CoordinateSystem {
+    Geographic,
+    Planar
+}
 
-pub fn crow_files<'a, T>() -> HeuristicFn<'a, T>
+impl CoordinateSystem {
+    // km for geographic coordinates, coordinate units for planar ones
+    pub fn distance<T>(&self, from: &Node<T>, to: &Node<T>) -> f64
+           where T: GraphKey {
+        match *self {
+            CoordinateSystem::Geographic => haversine(from.x, from.y, to.x, to.y),
+            CoordinateSystem::Planar => ((to.x - from.x).powi(2) + (to.y - from.y).powi(2)).sqrt()
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Metric {
+    // weights in seconds, at most this many distance units per hour
+    TravelTime(f64),
+    // weights in this many units per distance unit
+    Distance(f64)
+}
+
+fn scaled_distance<T>(from: &Node<T>, to: &Node<T>, coordinates: CoordinateSystem, metric: Metric) -> f64
+   where T: GraphKey {
+    let distance = coordinates.distance(from, to);
+    match metric {
+        Metric::TravelTime(max_speed) => distance / max_speed * 3600.0,
+        Metric::Distance(units) => distance * units
+    }
+}
+
+// rounded down, so an estimate never exceeds an edge whose weight is exact
+pub fn geometric_estimate<T>(from: &Node<T>, to: &Node<T>, coordinates: CoordinateSystem, metric: Metric) -> i64
+       where T: GraphKey {
+    scaled_distance(from, to, coordinates, metric).floor() as i64
+}
+
+pub fn geometric_heuristic<'a, T>(coordinates: CoordinateSystem, metric: Metric) -> HeuristicFn<'a, T>
        where T: 'a + GraphKey {
-    Box::new(|current: Option<&Node<T>>, target: Option<&Node<T>>| {
+    Box::new(move |current: Option<&Node<T>>, target: Option<&Node<T>>| {
         match (current, target) {
-            (Some(cnode), Some(tnode)) => {
-                road_weight(cnode, tnode, "motorway").unwrap_or(0)
-            }
+            (Some(cnode), Some(tnode)) => geometric_estimate(cnode, tnode, coordinates, metric),
             _ => 0
         }
     })
 }
 
+// edges the estimate overshoots before rounding down; without any, the triangle inequality
+// keeps the rounded estimate consistent and so admissible
+pub fn inadmissible_edges<T>(graph: &Graph<T>, coordinates: CoordinateSystem, metric: Metric) -> Vec<(T, T)>
+       where T: GraphKey {
+    graph.all_nodes()
+         .iter()
+         .flat_map(|node| graph.get_edges(&node.id).iter())
+         .filter(|edge|
+             match (graph.get_node(&edge.from_id), graph.get_node(&edge.to_id)) {
+                 (Some(from), Some(to)) => scaled_distance(from, to, coordinates, metric) > edge.weight as f64,
+                 _ => false
+             }
+         )
+         .map(|edge| (edge.from_id.clone(), edge.to_id.clone()))
+         .collect()
+}
+
+// the edges the estimate overshoots, if there are any
+pub fn build_geometric_heuristic<'a, T>(graph: &Graph<T>,
+                                        coordinates: CoordinateSystem,
+                                        metric: Metric) -> Result<HeuristicFn<'a, T>, Vec<(T, T)>>
+       where T: 'a + GraphKey {
+    let overshot = inadmissible_edges(graph, coordinates, metric);
+    if overshot.is_empty() {
+        Ok(geometric_heuristic(coordinates, metric))
+    } else {
+        Err(overshot)
+    }
+}
+
+// travel time at motorway speed over lng/lat coordinates
+pub fn crow_files<'a, T>() -> HeuristicFn<'a, T>
+       where T: 'a + GraphKey {
+    geometric_heuristic(CoordinateSystem::Geographic,
+                        Metric::TravelTime(ROAD_TYPE_SPEED["motorway"] as f64))
+}
+
 pub fn build_landmark_heuristic<'a, T>(graph: &Graph<T>, num_landmarks: usize) -> HeuristicFn<'a, T>
     where T: 'a + GraphKey {
         landmarks(
@@ -26,29 +105,400 @@ pub fn build_landmark_heuristic<'a, T>(graph: &Graph<T>, num_landmarks: usize) -
                 select_landmarks(graph, num_landmarks)))
 }
 
-fn landmarks<'a, T>(landmark_distances: Vec<HashMap<T, i64>>) -> HeuristicFn<'a, T>
+// the same heuristic, with the landmark distances swept from a contraction hierarchy
+pub fn build_landmark_heuristic_from_hierarchy<'a, T>(graph: &Graph<T>,
+                                                      hierarchy: &ContractionHierarchy<T>,
+                                                      num_landmarks: usize) -> HeuristicFn<'a, T>
+    where T: 'a + GraphKey {
+        landmarks(
+            build_landmark_distances_from_hierarchy(
+                hierarchy,
+                select_landmarks(graph, num_landmarks)))
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LandmarkSelection {
+    Random,
+    // the same seed picks the same landmarks on the same graph
+    SeededRandom(usize),
+    // each landmark as far as possible from the ones before it
+    FarthestPoint,
+    // grows landmarks where the current ones give the weakest bounds
+    Avoid,
+    // the farthest node in each of equal angular sectors around the graph's center
+    PlanarSectors
+}
+
+pub fn build_landmark_heuristic_with_selection<'a, T>(graph: &Graph<T>,
+                                                      num_landmarks: usize,
+                                                      selection: LandmarkSelection) -> HeuristicFn<'a, T>
+    where T: 'a + GraphKey + Ord {
+        landmarks(
+            build_landmark_distances(
+                graph,
+                select_landmarks_with(graph, num_landmarks, selection)))
+}
+
+pub fn select_landmarks_with<T>(graph: &Graph<T>, num_landmarks: usize, selection: LandmarkSelection) -> Vec<T>
+       where T: GraphKey + Ord {
+    let num_landmarks = num_landmarks.min(graph.all_nodes().len());
+    match selection {
+        LandmarkSelection::Random => select_landmarks(graph, num_landmarks),
+        LandmarkSelection::SeededRandom(seed) => seeded_landmarks(graph, num_landmarks, seed),
+        LandmarkSelection::FarthestPoint => farthest_landmarks(graph, num_landmarks),
+        LandmarkSelection::Avoid => avoid_landmarks(graph, num_landmarks),
+        LandmarkSelection::PlanarSectors => sector_landmarks(graph, num_landmarks)
+    }
+}
+
+fn seeded_landmarks<T>(graph: &Graph<T>, num_landmarks: usize, seed: usize) -> Vec<T>
+   where T: GraphKey + Ord {
+    let mut ids = graph.sorted_node_ids();
+    let seed: &[usize] = &[seed];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+
+    rng.shuffle(ids.as_mut_slice());
+    ids.into_iter().take(num_landmarks).collect()
+}
+
+// the node farthest from every landmark so far, starting from the one farthest from
+// the first node; nodes none of them reach count as farthest
+fn farthest_landmarks<T>(graph: &Graph<T>, num_landmarks: usize) -> Vec<T>
+   where T: GraphKey + Ord {
+    let ids = graph.sorted_node_ids();
+    let mut closest: HashMap<T, i64> = match ids.first() {
+        Some(start) => dijkstra_distances(graph, start),
+        None => return vec![]
+    };
+    let mut selected = vec![];
+    while selected.len() < num_landmarks {
+        let next = ids.iter()
+                      .enumerate()
+                      .filter(|&(_, id)| !selected.contains(id))
+                      // the first of equally far nodes wins
+                      .max_by_key(|&(i, id)| (closest.get(id).cloned().unwrap_or(i64::max_value()), -(i as i64)))
+                      .map(|(_, id)| id.clone())
+                      .unwrap();
+        if selected.is_empty() {
+            closest.clear();
+        }
+        for (id, distance) in dijkstra_distances(graph, &next) {
+            let entry = closest.entry(id).or_insert(distance);
+            if distance < *entry {
+                *entry = distance;
+            }
+        }
+        selected.push(next);
+    }
+    selected
+}
+
+// follows Goldberg and Harrelson: in a shortest path tree from a root, every node is
+// weighted by how much its distance beats the current lower bound; the heaviest
+// subtree without a landmark is followed down to a leaf, which becomes the next landmark
+fn avoid_landmarks<T>(graph: &Graph<T>, num_landmarks: usize) -> Vec<T>
+   where T: GraphKey + Ord {
+    let ids = graph.sorted_node_ids();
+    let mut selected = farthest_landmarks(graph, num_landmarks.min(1));
+    let mut distances = build_landmark_distances(graph, selected.clone());
+
+    let mut round = 0;
+    while selected.len() < num_landmarks && round < ids.len() {
+        let root = &ids[round];
+        round += 1;
+        let (_, tree) = shortest_path(graph, root, None);
+        let bound = landmarks(distances.clone());
+
+        let mut by_cost: Vec<&T> = tree.keys().collect();
+        by_cost.sort_by_key(|id| -tree[*id].cost);
+        let mut size: HashMap<T, i64> = HashMap::new();
+        let mut has_landmark: HashSet<T> = selected.iter().cloned().collect();
+        for id in &by_cost {
+            let node_weight = tree[*id].cost - bound(graph.get_node(root), graph.get_node(*id));
+            *size.entry((*id).clone()).or_insert(0) += node_weight;
+            let subtree = size[*id];
+            if let Some(ref parent) = tree[*id].predecessor {
+                *size.entry(parent.clone()).or_insert(0) += subtree;
+                if has_landmark.contains(*id) {
+                    has_landmark.insert(parent.clone());
+                }
+            }
+        }
+        let size_of = |id: &T| if has_landmark.contains(id) { 0 } else { size.get(id).cloned().unwrap_or(0) };
+
+        let mut current = match by_cost.iter().max_by_key(|id| size_of(id)) {
+            Some(id) if size_of(id) > 0 => (*id).clone(),
+            _ => continue
+        };
+        loop {
+            let child = tree.values()
+                            .filter(|best| best.predecessor.as_ref() == Some(&current))
+                            .max_by_key(|best| size_of(&best.id))
+                            .map(|best| best.id.clone());
+            match child {
+                Some(child) => current = child,
+                None => break
+            }
+        }
+        distances.append(&mut build_landmark_distances(graph, vec![current.clone()]));
+        selected.push(current);
+    }
+
+    // graphs too small to grow that many trees are filled up from the farthest points
+    for id in farthest_landmarks(graph, num_landmarks) {
+        if selected.len() >= num_landmarks {
+            break;
+        }
+        if !selected.contains(&id) {
+            selected.push(id);
+        }
+    }
+    selected
+}
+
+fn sector_landmarks<T>(graph: &Graph<T>, num_landmarks: usize) -> Vec<T>
+   where T: GraphKey + Ord {
+    let ids = graph.sorted_node_ids();
+    let nodes: Vec<&Node<T>> = ids.iter().filter_map(|id| graph.get_node(id)).collect();
+    if nodes.is_empty() || num_landmarks == 0 {
+        return vec![];
+    }
+    let count = nodes.len() as f64;
+    let center_x = nodes.iter().map(|node| node.x).sum::<f64>() / count;
+    let center_y = nodes.iter().map(|node| node.y).sum::<f64>() / count;
+    let squared_distance = |node: &Node<T>| (node.x - center_x).powi(2) + (node.y - center_y).powi(2);
+    let center = nodes.iter()
+                      .min_by(|a, b| squared_distance(a).partial_cmp(&squared_distance(b)).unwrap())
+                      .unwrap();
+    let from_center = dijkstra_distances(graph, &center.id);
+
+    let sector = |node: &Node<T>| {
+        let angle = (node.y - center_y).atan2(node.x - center_x) + PI;
+        ((angle / (2.0 * PI) * num_landmarks as f64) as usize).min(num_landmarks - 1)
+    };
+    // nodes the center cannot reach are the farthest of all
+    let mut by_distance: Vec<&Node<T>> = nodes.clone();
+    by_distance.sort_by_key(|node| -from_center.get(&node.id).cloned().unwrap_or(i64::max_value()));
+
+    let mut selected: Vec<T> = vec![];
+    for s in 0..num_landmarks {
+        if let Some(node) = by_distance.iter().find(|node| sector(node) == s) {
+            selected.push(node.id.clone());
+        }
+    }
+    for node in by_distance {
+        if selected.len() >= num_landmarks {
+            break;
+        }
+        if !selected.contains(&node.id) {
+            selected.push(node.id.clone());
+        }
+    }
+    selected
+}
+
+// distances from a landmark to every node and from every node to the landmark
+#[derive(Clone, PartialEq, Debug)]
+pub struct LandmarkDistances<T: GraphKey> {
+    pub from: HashMap<T, i64>,
+    pub to: HashMap<T, i64>
+}
+
+impl<T: GraphKey> LandmarkDistances<T> {
+    // by the triangle inequality d(v, t) >= d(L, t) - d(L, v) and d(v, t) >= d(v, L) - d(t, L)
+    pub fn lower_bound(&self, current: &T, target: &T) -> Option<i64> {
+        let through_from = self.from.get(target).and_then(|to_target|
+            self.from.get(current).map(|to_current| to_target - to_current)
+        );
+        let through_to = self.to.get(current).and_then(|from_current|
+            self.to.get(target).map(|from_target| from_current - from_target)
+        );
+        through_from.into_iter().chain(through_to).max()
+    }
+}
+
+fn landmarks<'a, T>(landmark_distances: Vec<LandmarkDistances<T>>) -> HeuristicFn<'a, T>
        where T: 'a + GraphKey {
     Box::new(move |current: Option<&Node<T>>, target: Option<&Node<T>>| {
         match (current, target) {
             (Some(c_node), Some(t_node)) => {
-                landmark_distances.iter().filter_map(|distances|
-                    distances.get(&c_node.id)
-                             .and_then(|dist|
-                                 distances.get(&t_node.id)
-                                          .map(|t_dist|
-                                               (dist - t_dist).abs()))
-                ).max().unwrap_or(0)
+                landmark_distances.iter()
+                                  .filter_map(|distances| distances.lower_bound(&c_node.id, &t_node.id))
+                                  .max()
+                                  .unwrap_or(0)
+                                  .max(0)
             }
             _ => 0
         }
     })
 }
 
-fn build_landmark_distances<T>(graph: &Graph<T>, landmarks: Vec<T>)
-   -> Vec<HashMap<T, i64>>
+pub fn build_active_landmark_heuristic<T>(graph: &Graph<T>,
+                                          num_landmarks: usize,
+                                          selection: LandmarkSelection,
+                                          active_count: usize,
+                                          refresh_every: Option<usize>) -> ActiveLandmarks<T>
+    where T: GraphKey + Ord {
+        ActiveLandmarks::new(
+            build_landmark_distances(
+                graph,
+                select_landmarks_with(graph, num_landmarks, selection)),
+            active_count,
+            refresh_every)
+}
+
+// landmark distances that search with only the active_count landmarks with the best
+// bounds from the query's source. A max over any subset of landmarks is still a lower bound.
+pub struct ActiveLandmarks<T: GraphKey> {
+    landmark_distances: Vec<LandmarkDistances<T>>,
+    active_count: usize,
+    refresh_every: Option<usize>
+}
+
+impl<T: GraphKey> ActiveLandmarks<T> {
+    pub fn new(landmark_distances: Vec<LandmarkDistances<T>>,
+               active_count: usize,
+               refresh_every: Option<usize>) -> Self {
+        ActiveLandmarks { landmark_distances: landmark_distances,
+                          active_count: active_count,
+                          refresh_every: refresh_every
+                        }
+    }
+
+    // the landmarks stay the same for the whole query, so the heuristic stays consistent;
+    // refreshing them needs the open nodes re-keyed, which only shortest_path does
+    pub fn for_query<'a>(&'a self, source: &T, target: &T) -> HeuristicFn<'a, T>
+           where T: 'a {
+        let active = self.initial_set(source, target);
+        Box::new(move |current: Option<&Node<T>>, target: Option<&Node<T>>| {
+            match (current, target) {
+                (Some(c_node), Some(t_node)) => self.bound(&active, &c_node.id, &t_node.id),
+                _ => 0
+            }
+        })
+    }
+
+    // A* over the active landmarks. With refresh_every, every so many settled nodes the
+    // landmark best for the node just settled takes the place of the weakest active one, and
+    // every open node is given a new key with the new landmarks. Each set of landmarks gives
+    // a consistent heuristic and all keys come from the same set, so paths stay optimal.
+    // Like dijkstra, the cost is 0 when there is no path.
+    pub fn shortest_path(&self, graph: &Graph<T>, source: &T, destination: &T) -> (i64, HashMap<T, CurrentBest<T>>) {
+        let (cost, results, _) = self.search(graph, source, destination);
+        (cost, results)
+    }
+
+    // also counts how often the active landmarks changed
+    fn search(&self, graph: &Graph<T>, source: &T, destination: &T) -> (i64, HashMap<T, CurrentBest<T>>, usize) {
+        let mut active = self.initial_set(source, destination);
+        let key = |active: &[usize], best: &CurrentBest<T>| CurrentBest {
+            id: best.id.clone(),
+            cost: best.cost + self.bound(active, &best.id, destination),
+            predecessor: best.predecessor.clone()
+        };
+        let mut results = HashMap::new();
+        let mut settled = HashSet::new();
+        let mut open = BinaryHeap::new();
+        let initial = CurrentBest { id: source.clone(), cost: 0, predecessor: None };
+        open.push(key(&active, &initial));
+        results.insert(source.clone(), initial);
+        let mut changes = 0;
+
+        while let Some(entry) = open.pop() {
+            if !settled.insert(entry.id.clone()) {
+                continue;
+            }
+            let current = results[&entry.id].clone();
+            if current.id == *destination {
+                return (current.cost, results, changes)
+            }
+            if self.refresh_every.map_or(false, |every| every > 0 && settled.len() % every == 0) &&
+               self.refresh(&mut active, &current.id, destination) {
+                changes += 1;
+                open = results.values()
+                              .filter(|best| !settled.contains(&best.id))
+                              .map(|best| key(&active, best))
+                              .collect();
+            }
+            for edge in graph.get_edges(&current.id) {
+                let cost = current.cost + edge.weight;
+                if !settled.contains(&edge.to_id) &&
+                   results.get(&edge.to_id).map_or(true, |best: &CurrentBest<T>| cost < best.cost) {
+                    let best = CurrentBest { id: edge.to_id.clone(), cost: cost, predecessor: Some(current.id.clone()) };
+                    open.push(key(&active, &best));
+                    results.insert(edge.to_id.clone(), best);
+                }
+            }
+        }
+        (0, results, changes)
+    }
+
+    fn initial_set(&self, source: &T, target: &T) -> Vec<usize> {
+        let mut active = self.best_first(source, target);
+        active.truncate(self.active_count);
+        active
+    }
+
+    // true if the active landmarks changed; there are never more than active_count of them
+    fn refresh(&self, active: &mut Vec<usize>, current: &T, target: &T) -> bool {
+        let best = match self.best_first(current, target).first() {
+            Some(&best) if !active.contains(&best) => best,
+            _ => return false
+        };
+        if active.len() < self.active_count {
+            active.push(best);
+            return true
+        }
+        let bound = |i: usize| self.landmark_distances[i].lower_bound(current, target).unwrap_or(i64::min_value());
+        match (0..active.len()).min_by_key(|&slot| bound(active[slot])) {
+            Some(weakest) => {
+                active[weakest] = best;
+                true
+            }
+            None => false
+        }
+    }
+
+    fn bound(&self, active: &[usize], current: &T, target: &T) -> i64 {
+        active.iter()
+              .filter_map(|&i| self.landmark_distances[i].lower_bound(current, target))
+              .max()
+              .unwrap_or(0)
+              .max(0)
+    }
+
+    fn best_first(&self, current: &T, target: &T) -> Vec<usize> {
+        let mut ranked: Vec<(i64, usize)> = self.landmark_distances
+                                                .iter()
+                                                .enumerate()
+                                                .filter_map(|(i, d)| d.lower_bound(current, target).map(|b| (b, i)))
+                                                .collect();
+        ranked.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        ranked.into_iter().map(|(_, i)| i).collect()
+    }
+}
+
+pub fn build_landmark_distances<T>(graph: &Graph<T>, landmarks: Vec<T>)
+   -> Vec<LandmarkDistances<T>>
    where T: GraphKey {
+       let reversed = graph.reversed();
        landmarks.iter().map(|landmark_id|
-           dijkstra_distances(graph, landmark_id)
+           LandmarkDistances { from: dijkstra_distances(graph, landmark_id),
+                               to: dijkstra_distances(&reversed, landmark_id)
+                             }
+       ).collect()
+}
+
+fn build_landmark_distances_from_hierarchy<T>(hierarchy: &ContractionHierarchy<T>, landmarks: Vec<T>)
+   -> Vec<LandmarkDistances<T>>
+   where T: GraphKey {
+       let reversed = hierarchy.reversed();
+       let forward = Phast::new(hierarchy);
+       let backward = Phast::new(&reversed);
+       landmarks.iter().map(|landmark_id|
+           LandmarkDistances { from: forward.distances(landmark_id),
+                               to: backward.distances(landmark_id)
+                             }
        ).collect()
 }
 
@@ -74,10 +524,22 @@ mod test {
     use std::collections::HashMap;
     use weighted_graph::{ Graph, Node };
     use road_weights::road_weight;
-    use super::{ crow_files,
+    use contraction::preprocess_contraction;
+    use dijkstra::shortest_path as dijkstra;
+    use super::{ LandmarkSelection,
+                 CoordinateSystem,
+                 Metric,
+                 crow_files,
+                 geometric_estimate,
+                 inadmissible_edges,
+                 build_geometric_heuristic,
+                 geometric_heuristic,
                  select_landmarks,
+                 select_landmarks_with,
                  build_landmark_distances,
-                 landmarks
+                 build_landmark_distances_from_hierarchy,
+                 landmarks,
+                 ActiveLandmarks
                };
 
     fn build_graph() -> Graph<&'static str> {
@@ -115,8 +577,8 @@ mod test {
 
     #[test]
     fn calculate_crow_flying_distance() {
-        let node_1 = Node { id: "1", x: 0.0, y: 0.0, contraction_order: None };
-        let node_2 = Node { id: "2", x: 1.0, y: 1.0, contraction_order: None };
+        let node_1 = Node { id: "1", x: 0.0, y: 0.0 };
+        let node_2 = Node { id: "2", x: 1.0, y: 1.0 };
 
         let heuristic = crow_files();
 
@@ -128,7 +590,7 @@ mod test {
 
     #[test]
     fn crow_flies_0_if_none() {
-        let node_1 = Node { id: "1", x: 0.0, y: 0.0, contraction_order: None };
+        let node_1 = Node { id: "1", x: 0.0, y: 0.0 };
 
         let heuristic = crow_files();
 
@@ -177,7 +639,20 @@ mod test {
 
         let expected = vec![results_2, results_3];
 
-        assert_eq!(distances, expected);
+        // every edge has a twin of the same weight, so distances to and from agree
+        assert_eq!(distances.iter().map(|d| d.from.clone()).collect::<Vec<_>>(), expected);
+        assert_eq!(distances.iter().map(|d| d.to.clone()).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn build_distances_to_landmarks_from_hierarchy() {
+        let graph = build_graph();
+        let hierarchy = preprocess_contraction(&graph);
+
+        let landmark_nodes = vec!["2", "3"];
+
+        assert_eq!(build_landmark_distances_from_hierarchy(&hierarchy, landmark_nodes.clone()),
+                   build_landmark_distances(&graph, landmark_nodes));
     }
 
     #[test]
@@ -194,4 +669,251 @@ mod test {
 
         assert_eq!(heuristic(node_1, node_6), 4);
     }
+
+    #[test]
+    fn every_selection_picks_distinct_graph_nodes() {
+        let graph = build_graph();
+        let selections = vec![LandmarkSelection::Random,
+                              LandmarkSelection::SeededRandom(7),
+                              LandmarkSelection::FarthestPoint,
+                              LandmarkSelection::Avoid,
+                              LandmarkSelection::PlanarSectors];
+
+        for selection in selections {
+            for n in 1..7 {
+                let landmarks = select_landmarks_with(&graph, n, selection);
+                assert_eq!(landmarks.len(), n);
+                assert!(landmarks.iter().all(|landmark| graph.get_node(landmark).is_some()));
+                for (i, landmark) in landmarks.iter().enumerate() {
+                    assert!(!landmarks[i + 1..].contains(landmark));
+                }
+            }
+            assert_eq!(select_landmarks_with(&graph, 10, selection).len(), 6);
+        }
+    }
+
+    #[test]
+    fn deterministic_selections_repeat() {
+        let graph = build_graph();
+
+        for selection in vec![LandmarkSelection::SeededRandom(3),
+                              LandmarkSelection::FarthestPoint,
+                              LandmarkSelection::Avoid,
+                              LandmarkSelection::PlanarSectors] {
+            assert_eq!(select_landmarks_with(&graph, 3, selection),
+                       select_landmarks_with(&graph, 3, selection));
+        }
+    }
+
+    #[test]
+    fn farthest_point_spreads_landmarks() {
+        let graph = build_graph();
+
+        assert_eq!(select_landmarks_with(&graph, 3, LandmarkSelection::FarthestPoint),
+                   vec!["6", "1", "4"]);
+    }
+
+    fn build_one_way_graph() -> Graph<&'static str> {
+        let mut graph = Graph::new();
+        for &(id, x, y) in &[("1", 1.0, 1.0), ("2", 2.0, 4.0), ("3", 3.0, 2.0), ("4", 4.0, 1.0)] {
+            graph.add_node(id, x, y);
+        }
+        // a cheap one-way loop 1 -> 2 -> 3 -> 4 -> 1, with an expensive way back from 2 to 1
+        let edges = vec![("a", "1", "2", 1),
+                         ("b", "2", "3", 1),
+                         ("c", "3", "4", 1),
+                         ("d", "4", "1", 1),
+                         ("e", "2", "1", 10)];
+        for (edge_id, from, to, cost) in edges {
+            graph.add_edge(edge_id, from, to, cost);
+        }
+        graph
+    }
+
+    #[test]
+    fn directed_landmark_bounds_stay_admissible() {
+        let graph = build_one_way_graph();
+        let node_ids = vec!["1", "2", "3", "4"];
+
+        for landmark in &node_ids {
+            let heuristic = landmarks(build_landmark_distances(&graph, vec![*landmark]));
+            for from in &node_ids {
+                for to in &node_ids {
+                    let (cost, _) = dijkstra(&graph, from, Some(to));
+                    assert!(heuristic(graph.get_node(from), graph.get_node(to)) <= cost);
+                }
+            }
+        }
+
+        // d(1, 2) - d(1, 1) is the only bound landmark 1 gives from 1 to 2
+        let heuristic = landmarks(build_landmark_distances(&graph, vec!["1"]));
+        assert_eq!(heuristic(graph.get_node(&"1"), graph.get_node(&"2")), 1);
+        // and d(2, 1) - d(1, 1) from 2 back to 1
+        assert_eq!(heuristic(graph.get_node(&"2"), graph.get_node(&"1")), 3);
+    }
+
+    #[test]
+    fn directed_distances_from_hierarchy() {
+        let graph = build_one_way_graph();
+        let hierarchy = preprocess_contraction(&graph);
+
+        let distances = build_landmark_distances_from_hierarchy(&hierarchy, vec!["2"]);
+
+        assert_eq!(distances, build_landmark_distances(&graph, vec!["2"]));
+        assert_eq!(distances[0].from.get(&"1"), Some(&3));
+        assert_eq!(distances[0].to.get(&"1"), Some(&1));
+    }
+
+    #[test]
+    fn active_landmarks_chosen_at_the_source() {
+        let graph = build_graph();
+        let node = |id| graph.get_node(&id);
+        let all = landmarks(build_landmark_distances(&graph, vec!["2", "3"]));
+        let active = ActiveLandmarks::new(build_landmark_distances(&graph, vec!["2", "3"]), 1, None);
+
+        // landmark 3 is best from 1 to 6, landmark 2 from 2 to 6
+        let from_1 = active.for_query(&"1", &"6");
+        assert_eq!(from_1(node("1"), node("6")), all(node("1"), node("6")));
+        assert_eq!(all(node("2"), node("6")), 2);
+        assert_eq!(from_1(node("2"), node("6")), 1);
+        // another source with the same target picks again
+        assert_eq!(active.for_query(&"2", &"6")(node("2"), node("6")), 2);
+        assert_eq!(active.for_query(&"6", &"2")(node("6"), node("2")), all(node("6"), node("2")));
+    }
+
+    // a size x size grid of unevenly weighted two-way streets
+    fn build_grid_graph(size: i64) -> (Vec<String>, Graph<String>) {
+        let mut graph = Graph::new();
+        let id = |x: i64, y: i64| format!("{}-{}", x, y);
+        let mut nodes = vec![];
+        for x in 0..size {
+            for y in 0..size {
+                graph.add_node(id(x, y), x as f64, y as f64);
+                nodes.push(id(x, y));
+            }
+        }
+        for x in 0..size {
+            for y in 0..size {
+                for &(nx, ny) in &[(x + 1, y), (x, y + 1)] {
+                    if nx < size && ny < size {
+                        let weight = 1 + (x * 7 + y * 3) % 5;
+                        graph.add_edge(id(x, y), id(x, y), id(nx, ny), weight);
+                        graph.add_edge(id(nx, ny), id(nx, ny), id(x, y), weight);
+                    }
+                }
+            }
+        }
+        (nodes, graph)
+    }
+
+    #[test]
+    fn refreshed_landmarks_keep_paths_optimal() {
+        let (nodes, graph) = build_grid_graph(5);
+        let chosen = vec!["0-0", "0-4", "4-0", "4-4", "2-0"].into_iter().map(|id| id.to_string()).collect();
+        let distances = build_landmark_distances(&graph, chosen);
+        let mut changes = 0;
+
+        for refresh_every in vec![None, Some(1), Some(3)] {
+            for active_count in vec![1, 2] {
+                let active = ActiveLandmarks::new(distances.clone(), active_count, refresh_every);
+                for from in &nodes {
+                    let (_, expected) = dijkstra(&graph, from, None);
+                    for to in &nodes {
+                        let (cost, results, changed) = active.search(&graph, from, to);
+                        assert_eq!(cost, expected[to].cost);
+                        assert_eq!(results[to].cost, expected[to].cost);
+                        assert_eq!(active.shortest_path(&graph, from, to).0, cost);
+                        changes += changed;
+                    }
+                }
+            }
+        }
+        // the refreshes did swap landmarks on the way
+        assert!(changes > 0);
+    }
+
+    #[test]
+    fn active_landmarks_stay_admissible() {
+        let graph = build_one_way_graph();
+        let node_ids = vec!["1", "2", "3", "4"];
+        let distances = build_landmark_distances(&graph, node_ids.clone());
+        let active = ActiveLandmarks::new(distances, 2, None);
+
+        for to in &node_ids {
+            for from in &node_ids {
+                let heuristic = active.for_query(from, to);
+                for current in &node_ids {
+                    let (cost, _) = dijkstra(&graph, current, Some(to));
+                    assert!(heuristic(graph.get_node(current), graph.get_node(to)) <= cost);
+                }
+                assert_eq!(active.shortest_path(&graph, from, to).0, dijkstra(&graph, from, Some(to)).0);
+            }
+        }
+    }
+
+    #[test]
+    fn planar_distances_in_coordinate_units() {
+        let node_1 = Node { id: "1", x: 0.0, y: 0.0 };
+        let node_2 = Node { id: "2", x: 3.0, y: 4.0 };
+
+        assert_eq!(geometric_estimate(&node_1, &node_2, CoordinateSystem::Planar, Metric::Distance(1.0)), 5);
+        assert_eq!(geometric_estimate(&node_1, &node_2, CoordinateSystem::Planar, Metric::Distance(0.5)), 2);
+        assert_eq!(geometric_estimate(&node_1, &node_2, CoordinateSystem::Planar, Metric::TravelTime(3600.0)), 5);
+    }
+
+    #[test]
+    fn geographic_time_matches_road_weights() {
+        let node_1 = Node { id: "1", x: -71.085743, y: 42.343212 };
+        let node_2 = Node { id: "2", x: -71.087792, y: 42.347249 };
+
+        assert_eq!(geometric_estimate(&node_1, &node_2, CoordinateSystem::Geographic, Metric::TravelTime(50.0)),
+                   road_weight(&node_1, &node_2, "tertiary").unwrap());
+    }
+
+    #[test]
+    fn check_heuristic_admissibility() {
+        let graph = build_graph();
+        let node_ids = vec!["1", "2", "3", "4", "5", "6"];
+
+        let mut overshot = inadmissible_edges(&graph, CoordinateSystem::Planar, Metric::Distance(1.0));
+        overshot.sort();
+        assert_eq!(overshot, vec![("2", "6"), ("6", "2")]);
+        match build_geometric_heuristic(&graph, CoordinateSystem::Planar, Metric::Distance(1.0)) {
+            Err(mut edges) => {
+                edges.sort();
+                assert_eq!(edges, overshot);
+            }
+            Ok(_) => panic!("expected the overshot edges")
+        }
+
+        let heuristic = match build_geometric_heuristic(&graph, CoordinateSystem::Planar, Metric::Distance(0.5)) {
+            Ok(heuristic) => heuristic,
+            Err(edges) => panic!("unexpected overshot edges {:?}", edges)
+        };
+        for from in &node_ids {
+            for to in &node_ids {
+                let (cost, _) = dijkstra(&graph, from, Some(to));
+                assert!(heuristic(graph.get_node(from), graph.get_node(to)) <= cost);
+            }
+        }
+    }
+
+    #[test]
+    fn rounding_down_does_not_hide_overshooting_edges() {
+        // each edge rounds down to its weight of 1, but the three together round down to 5
+        let mut graph = Graph::new();
+        for (i, id) in vec!["1", "2", "3", "4"].into_iter().enumerate() {
+            graph.add_node(id, 1.9 * i as f64, 0.0);
+        }
+        for &(from, to) in &[("1", "2"), ("2", "3"), ("3", "4")] {
+            graph.add_edge(from, from, to, 1);
+            graph.add_edge(to, to, from, 1);
+        }
+        let estimate = geometric_heuristic(CoordinateSystem::Planar, Metric::Distance(1.0));
+
+        assert_eq!(estimate(graph.get_node(&"1"), graph.get_node(&"4")), 5);
+        assert_eq!(dijkstra(&graph, &"1", Some(&"4")).0, 3);
+        assert_eq!(inadmissible_edges(&graph, CoordinateSystem::Planar, Metric::Distance(1.0)).len(), 6);
+        assert!(inadmissible_edges(&graph, CoordinateSystem::Planar, Metric::Distance(0.5)).is_empty());
+    }
 }
@@ -1,7 +1,8 @@
-use std::collections::HashMap;
-use weighted_graph::{ GraphKey, Graph, Node };
+use std::collections::{ HashMap, HashSet };
+use weighted_graph::{ GraphKey, Graph, Node, Edge };
 use dijkstra::shortest_path as dijkstra;
 use pathfinder::{ CurrentBest, Pathfinder, EdgeIterator };
+use partition::Partition;
 
 pub fn shortest_path<'a, T>(graph: &'a Graph<T>,
                             source: &T,
@@ -21,6 +22,10 @@ pub fn shortest_path<'a, T>(graph: &'a Graph<T>,
     pathfinder.shortest_path(graph, source, destination)
 }
 
+pub trait Region<T: GraphKey> {
+    fn contains(&self, node: &Node<T>) -> bool;
+}
+
 pub struct Rect {
     x_max: f64,
     x_min: f64,
@@ -28,9 +33,8 @@ pub struct Rect {
     y_min: f64
 }
 
-impl Rect {
-    pub fn contains<T>(&self, node: &Node<T>) -> bool
-           where T: GraphKey {
+impl<T: GraphKey> Region<T> for Rect {
+    fn contains(&self, node: &Node<T>) -> bool {
         node.x <= self.x_max &&
             node.x >= self.x_min &&
             node.y <= self.y_max &&
@@ -38,51 +42,211 @@ impl Rect {
     }
 }
 
-pub fn assign_arc_flags<T>(graph: &mut Graph<T>, region: Rect)
-       where T: GraphKey {
-    let internal = &internal_nodes(graph, &region)[..];
-    let results = inbound_paths(graph, internal, &region);
-    for result in results {
-        if let Some(predecessor) = result.predecessor {
-            graph.get_mut_edge(&result.id, &predecessor)
-                .map(|edge| edge.arc_flag = true);
+// corners as (x, y), in order around the outline
+pub struct Polygon {
+    corners: Vec<(f64, f64)>
+}
+
+impl Polygon {
+    pub fn new(corners: Vec<(f64, f64)>) -> Self {
+        Polygon { corners: corners }
+    }
+}
+
+impl<T: GraphKey> Region<T> for Polygon {
+    // counts the sides crossed by a ray running from the node towards positive x
+    fn contains(&self, node: &Node<T>) -> bool {
+        let mut inside = false;
+        let count = self.corners.len();
+        for i in 0..count {
+            let (x1, y1) = self.corners[i];
+            let (x2, y2) = self.corners[(i + 1) % count];
+            if (y1 > node.y) != (y2 > node.y) &&
+                node.x < x1 + (node.y - y1) * (x2 - x1) / (y2 - y1) {
+                inside = !inside;
+            }
         }
+        inside
     }
+}
+
+pub struct NodeSet<T: GraphKey> {
+    nodes: HashSet<T>
+}
+
+impl<T: GraphKey> NodeSet<T> {
+    pub fn new(nodes: HashSet<T>) -> Self {
+        NodeSet { nodes: nodes }
+    }
+
+    pub fn from_cell(partition: &Partition<T>, cell: usize) -> Self {
+        NodeSet::new(partition.nodes_in_cell(cell).into_iter().collect())
+    }
+}
+
+impl<T: GraphKey> Region<T> for NodeSet<T> {
+    fn contains(&self, node: &Node<T>) -> bool {
+        self.nodes.contains(&node.id)
+    }
+}
+
+pub fn assign_arc_flags<T, R>(graph: &mut Graph<T>, region: R)
+       where T: GraphKey,
+             R: Region<T> {
+    let flagged = flagged_edges(graph, &|node: &Node<T>| region.contains(node));
+    for (from_id, to_id) in flagged {
+        graph.get_mut_edge(&from_id, &to_id).map(|edge| edge.arc_flag = true);
+    }
+}
+
+// edges on a shortest path into the region, along with the edges inside it
+fn flagged_edges<T, F>(graph: &Graph<T>, in_region: &F) -> Vec<(T, T)>
+   where T: GraphKey,
+         F: Fn(&Node<T>) -> bool {
+    let internal = internal_nodes(graph, in_region);
+    let trees = boundary_trees(&graph.reversed(), &internal, in_region);
+    region_edges(graph, &internal, &trees)
+}
+
+fn region_edges<T>(graph: &Graph<T>, internal: &[T], trees: &HashMap<T, HashMap<T, CurrentBest<T>>>) -> Vec<(T, T)>
+   where T: GraphKey {
+    let mut flagged: Vec<(T, T)> = trees.values()
+                                        .flat_map(|tree| tree.values())
+                                        .filter_map(|result|
+                                            result.predecessor.clone().map(|predecessor| (result.id.clone(), predecessor))
+                                        )
+                                        .collect();
 
     for from_id in internal {
         for to_id in internal {
-            graph.get_mut_edge(&from_id, &to_id).map(|edge| edge.arc_flag = true);
+            if graph.get_edges(from_id).iter().any(|edge| edge.to_id == *to_id) {
+                flagged.push((from_id.clone(), to_id.clone()));
+            }
         }
     }
+    flagged
 }
 
-fn inbound_paths<T>(graph: &Graph<T>, node_ids: &[T], region: &Rect) -> Vec<CurrentBest<T>>
-   where T: GraphKey {
+// backward searches over the reversed graph from every node entered from outside
+// the region; each predecessor is the next hop of an original edge towards it
+fn boundary_trees<T, F>(reversed: &Graph<T>, node_ids: &[T], in_region: &F) -> HashMap<T, HashMap<T, CurrentBest<T>>>
+   where T: GraphKey,
+         F: Fn(&Node<T>) -> bool {
     node_ids.iter()
-            .filter(|node_id| boundary_node(graph, region, *node_id))
-            .flat_map(|node_id|
-                dijkstra(graph, &node_id, None).1.into_iter()
-                    .map(|(_, v)| v)
-                ).collect()
+            .filter(|node_id| is_boundary(reversed, in_region, *node_id))
+            .map(|node_id| (node_id.clone(), dijkstra(reversed, node_id, None).1))
+            .collect()
 }
 
-fn internal_nodes<T>(graph: &Graph<T>, rect: &Rect) -> Vec<T>
-   where T: GraphKey {
+fn internal_nodes<T, F>(graph: &Graph<T>, in_region: &F) -> Vec<T>
+   where T: GraphKey,
+         F: Fn(&Node<T>) -> bool {
     graph.all_nodes()
          .into_iter()
-         .filter(|node_ref| rect.contains(node_ref))
+         .filter(|node_ref| in_region(node_ref))
          .map(|node_ref| node_ref.id.clone())
          .collect::<Vec<T>>()
 }
 
-fn boundary_node<T>(graph: &Graph<T>, rect: &Rect, node_id: &T) -> bool
+// keeps the shortest path tree of every boundary node, so that after a few weight
+// changes only the trees those changes touch have to be searched again
+pub struct ArcFlagTrees<T: GraphKey> {
+    internal: Vec<T>,
+    trees: HashMap<T, HashMap<T, CurrentBest<T>>>
+}
+
+impl<T: GraphKey> ArcFlagTrees<T> {
+    pub fn new<R>(graph: &Graph<T>, region: &R) -> Self
+           where R: Region<T> {
+        let in_region = |node: &Node<T>| region.contains(node);
+        let internal = internal_nodes(graph, &in_region);
+        let trees = boundary_trees(&graph.reversed(), &internal, &in_region);
+        ArcFlagTrees { internal: internal, trees: trees }
+    }
+
+    pub fn boundary_nodes(&self) -> Vec<T> {
+        self.trees.keys().cloned().collect()
+    }
+
+    // resets every arc flag of the graph to the ones of the region
+    pub fn apply(&self, graph: &mut Graph<T>) {
+        let edges: Vec<(T, T)> = graph.all_nodes()
+                                      .iter()
+                                      .flat_map(|node| graph.get_edges(&node.id).iter())
+                                      .map(|edge| (edge.from_id.clone(), edge.to_id.clone()))
+                                      .collect();
+        for (from_id, to_id) in edges {
+            graph.get_mut_edge(&from_id, &to_id).map(|edge| edge.arc_flag = false);
+        }
+        for (from_id, to_id) in region_edges(graph, &self.internal, &self.trees) {
+            graph.get_mut_edge(&from_id, &to_id).map(|edge| edge.arc_flag = true);
+        }
+    }
+
+    // changes are (from_id, to_id, new weight); returns the boundary nodes searched again
+    pub fn update_edge_weights(&mut self, graph: &mut Graph<T>, changes: &[(T, T, i64)]) -> Vec<T> {
+        let mut affected = HashSet::new();
+        for &(ref from_id, ref to_id, weight) in changes {
+            let old_weight = match graph.get_mut_edge(from_id, to_id) {
+                Some(edge) => edge.weight,
+                None => continue
+            };
+            for (boundary_id, tree) in &self.trees {
+                if tree_is_affected(tree, from_id, to_id, old_weight, weight) {
+                    affected.insert(boundary_id.clone());
+                }
+            }
+            graph.get_mut_edge(from_id, to_id).map(|edge| edge.weight = weight);
+        }
+
+        if !affected.is_empty() {
+            let reversed = graph.reversed();
+            for boundary_id in &affected {
+                self.trees.insert(boundary_id.clone(), dijkstra(&reversed, boundary_id, None).1);
+            }
+        }
+        self.apply(graph);
+        affected.into_iter().collect()
+    }
+}
+
+// a tree changes when one of its edges changes weight, or when a cheaper
+// edge offers a shorter way from its start to the tree's boundary node
+fn tree_is_affected<T>(tree: &HashMap<T, CurrentBest<T>>, from_id: &T, to_id: &T, old_weight: i64, weight: i64) -> bool
    where T: GraphKey {
+    let in_tree = tree.get(from_id).and_then(|best| best.predecessor.as_ref()) == Some(to_id);
+    if in_tree {
+        return weight != old_weight
+    }
+    weight < old_weight &&
+        tree.get(to_id).map_or(false, |to| {
+            tree.get(from_id).map_or(true, |from| to.cost + weight < from.cost)
+        })
+}
+
+pub fn assign_maintained_arc_flags<T, R>(graph: &mut Graph<T>, region: &R) -> ArcFlagTrees<T>
+       where T: GraphKey,
+             R: Region<T> {
+    let trees = ArcFlagTrees::new(graph, region);
+    trees.apply(graph);
+    trees
+}
+
+pub fn boundary_node<T, R>(graph: &Graph<T>, region: &R, node_id: &T) -> bool
+   where T: GraphKey,
+         R: Region<T> {
+    is_boundary(graph, &|node: &Node<T>| region.contains(node), node_id)
+}
+
+fn is_boundary<T, F>(graph: &Graph<T>, in_region: &F, node_id: &T) -> bool
+   where T: GraphKey,
+         F: Fn(&Node<T>) -> bool {
     match graph.get_node(node_id) {
         Some(node) => {
-            rect.contains(node) &&
+            in_region(node) &&
             graph.get_edges(&node.id).iter().any(|edge|
                 graph.get_node(&edge.to_id).map(|node|
-                    !rect.contains(node)
+                    !in_region(node)
                 ).unwrap_or(false)
             )
         }
@@ -90,13 +254,141 @@ fn boundary_node<T>(graph: &Graph<T>, rect: &Rect, node_id: &T) -> bool
     }
 }
 
+// one bit per region
+#[derive(Clone, PartialEq, Debug)]
+pub struct RegionFlags {
+    bits: Vec<u64>
+}
+
+impl RegionFlags {
+    pub fn new(region_count: usize) -> Self {
+        RegionFlags { bits: vec![0; (region_count + 63) / 64] }
+    }
+
+    pub fn set(&mut self, region: usize) {
+        self.bits[region / 64] |= 1 << (region % 64);
+    }
+
+    pub fn get(&self, region: usize) -> bool {
+        self.bits.get(region / 64).map_or(false, |word| word & (1 << (region % 64)) != 0)
+    }
+}
+
+// arc flags for every region of a partition, kept apart from the graph's single arc flag
+#[derive(Debug)]
+pub struct MultiRegionArcFlags<T: GraphKey> {
+    regions: HashMap<T, usize>,
+    region_count: usize,
+    flags: HashMap<(T, T), RegionFlags>
+}
+
+impl<T: GraphKey> MultiRegionArcFlags<T> {
+    pub fn region(&self, node_id: &T) -> Option<usize> {
+        self.regions.get(node_id).cloned()
+    }
+
+    pub fn region_count(&self) -> usize {
+        self.region_count
+    }
+
+    pub fn flags(&self, from_id: &T, to_id: &T) -> Option<&RegionFlags> {
+        self.flags.get(&(from_id.clone(), to_id.clone()))
+    }
+
+    pub fn is_flagged(&self, edge: &Edge<T>, region: usize) -> bool {
+        self.flags(&edge.from_id, &edge.to_id).map_or(false, |flags| flags.get(region))
+    }
+}
+
+// regions maps every node to a region numbered from 0
+pub fn assign_region_arc_flags<T>(graph: &Graph<T>, regions: HashMap<T, usize>) -> MultiRegionArcFlags<T>
+       where T: GraphKey {
+    let region_count = regions.values().max().map_or(0, |&max| max + 1);
+    let mut flags: HashMap<(T, T), RegionFlags> = HashMap::new();
+
+    for region in 0..region_count {
+        let in_region = |node: &Node<T>| regions.get(&node.id) == Some(&region);
+        for key in flagged_edges(graph, &in_region) {
+            flags.entry(key).or_insert(RegionFlags::new(region_count)).set(region);
+        }
+    }
+
+    MultiRegionArcFlags { regions: regions,
+                          region_count: region_count,
+                          flags: flags
+                        }
+}
+
+pub fn assign_partition_arc_flags<T>(graph: &Graph<T>, partition: &Partition<T>) -> MultiRegionArcFlags<T>
+       where T: GraphKey {
+    assign_region_arc_flags(graph, partition.cells().clone())
+}
+
+// splits the bounding box of the graph's nodes into columns x rows equal regions
+pub fn grid_regions<T>(graph: &Graph<T>, columns: usize, rows: usize) -> HashMap<T, usize>
+       where T: GraphKey {
+    let nodes = graph.all_nodes();
+    let x_min = nodes.iter().map(|node| node.x).fold(f64::INFINITY, f64::min);
+    let x_max = nodes.iter().map(|node| node.x).fold(f64::NEG_INFINITY, f64::max);
+    let y_min = nodes.iter().map(|node| node.y).fold(f64::INFINITY, f64::min);
+    let y_max = nodes.iter().map(|node| node.y).fold(f64::NEG_INFINITY, f64::max);
+
+    let cell = |value: f64, min: f64, max: f64, count: usize| {
+        if max > min {
+            (((value - min) / (max - min) * count as f64) as usize).min(count - 1)
+        } else {
+            0
+        }
+    };
+    nodes.iter()
+         .map(|node| (node.id.clone(),
+                      cell(node.x, x_min, x_max, columns) +
+                          columns * cell(node.y, y_min, y_max, rows)))
+         .collect()
+}
+
+// only follows edges flagged for the destination's region
+pub fn multi_region_shortest_path<'a, T>(graph: &'a Graph<T>,
+                                         arc_flags: &'a MultiRegionArcFlags<T>,
+                                         source: &T,
+                                         destination: &T
+                                        ) -> (i64, HashMap<T, CurrentBest<T>>)
+       where T: GraphKey {
+    let region = match arc_flags.region(destination) {
+        Some(region) => region,
+        None => return dijkstra(graph, source, Some(destination))
+    };
+    let identity = |_: Option<&Node<T>>, _ :Option<&Node<T>>| 0;
+    let edge_iterator = move |g: &'a Graph<T>, node_id: &T| ->
+                        EdgeIterator<'a, T> {
+        Box::new(g.get_edges(node_id).iter().filter(move |edge| arc_flags.is_flagged(edge, region)))
+    };
+    let terminator = |_: &CurrentBest<T>, _: &HashMap<T, CurrentBest<T>>| false;
+    let pathfinder = Pathfinder::new(Box::new(identity),
+                                     Box::new(edge_iterator),
+                                     Box::new(terminator)
+                                    );
+    pathfinder.shortest_path(graph, source, Some(destination))
+}
+
 #[cfg(test)]
 mod test {
     use std::collections::HashSet;
     use weighted_graph::{ Graph, Node };
-    use super::{ Rect,
+    use dijkstra::shortest_path as dijkstra;
+    use partition::inertial_flow_partition;
+    use super::{ Region,
+                 Rect,
+                 Polygon,
+                 NodeSet,
+                 assign_maintained_arc_flags,
+                 RegionFlags,
                  boundary_node,
                  assign_arc_flags,
+                 assign_partition_arc_flags,
+                 assign_region_arc_flags,
+                 grid_regions,
+                 multi_region_shortest_path,
                  shortest_path
                };
 
@@ -127,12 +419,40 @@ mod test {
         graph
     }
 
+    // one-way streets and uneven weights, so no edge has an equal reverse twin
+    fn build_one_way_graph() -> Graph<&'static str> {
+        let mut graph = Graph::new();
+        graph.add_node("1", 1.0, 2.0);
+        graph.add_node("2", 2.0, 1.0);
+        graph.add_node("3", 2.0, 2.0);
+        graph.add_node("4", 3.0, 3.0);
+        graph.add_node("5", 3.0, 4.0);
+        graph.add_node("6", 4.0, 3.0);
+
+        let edges = vec![("a", "1", "5", 1),
+                         ("b", "5", "6", 1),
+                         ("c", "6", "2", 4),
+                         ("d", "2", "6", 2),
+                         ("e", "2", "4", 1),
+                         ("f", "4", "3", 1),
+                         ("g", "3", "1", 5),
+                         ("h", "6", "4", 7),
+                         ("i", "4", "5", 2),
+                         ("j", "3", "2", 3)];
+
+        for (edge_id, node_id_1, node_id_2, cost) in edges {
+            graph.add_edge(edge_id, node_id_1, node_id_2, cost);
+        }
+
+        graph
+    }
+
     #[test]
     fn node_contains_rectangle() {
         let rect = Rect { x_min: 0.0, x_max: 5.0, y_min: 0.0, y_max: 5.0 };
-        let contains = Node { id: "contains", x: 1.0, y: 1.0, contraction_order: None };
-        let outside = Node { id: "outside", x: 10.0, y: 10.0, contraction_order: None };
-        let border = Node { id: "border", x: 0.0, y: 3.0, contraction_order: None };
+        let contains = Node { id: "contains", x: 1.0, y: 1.0 };
+        let outside = Node { id: "outside", x: 10.0, y: 10.0 };
+        let border = Node { id: "border", x: 0.0, y: 3.0 };
 
         assert!(rect.contains(&contains));
         assert!(!rect.contains(&outside));
@@ -199,4 +519,242 @@ mod test {
         assert!(!results.values().any(|r| r.id == "5"));
         assert_eq!(cost, 5)
     }
+
+    #[test]
+    fn set_region_bits() {
+        let mut flags = RegionFlags::new(70);
+        flags.set(0);
+        flags.set(65);
+
+        assert!(flags.get(0));
+        assert!(flags.get(65));
+        assert!(!flags.get(1));
+        assert!(!flags.get(64));
+        assert!(!flags.get(200));
+    }
+
+    #[test]
+    fn split_graph_into_grid_regions() {
+        let graph = build_graph();
+
+        let regions = grid_regions(&graph, 2, 2);
+
+        assert_eq!(regions.len(), 6);
+        assert_eq!(regions.get(&"1"), Some(&0));
+        assert_eq!(regions.get(&"2"), Some(&0));
+        assert_eq!(regions.get(&"4"), Some(&3));
+        assert_eq!(regions.get(&"6"), Some(&3));
+        assert_eq!(regions.get(&"5"), Some(&3));
+        assert_eq!(grid_regions(&graph, 1, 1).values().max(), Some(&0));
+    }
+
+    #[test]
+    fn region_flags_match_single_region_flags() {
+        let mut graph = build_graph();
+        let region = Rect { x_min: 1.5,
+                            x_max: 3.5,
+                            y_min: 1.5,
+                            y_max: 3.5
+                          };
+        let regions = graph.all_nodes()
+                           .iter()
+                           .map(|node| (node.id, if region.contains(node) { 1 } else { 0 }))
+                           .collect();
+
+        let arc_flags = assign_region_arc_flags(&graph, regions);
+        assign_arc_flags(&mut graph, region);
+
+        assert_eq!(arc_flags.region_count(), 2);
+        for node in graph.all_nodes() {
+            for edge in graph.get_edges(&node.id) {
+                assert_eq!(arc_flags.is_flagged(edge, 1), edge.arc_flag);
+            }
+        }
+    }
+
+    #[test]
+    fn multi_region_queries_match_dijkstra() {
+        let graph = build_graph();
+        let arc_flags = assign_region_arc_flags(&graph, grid_regions(&graph, 2, 2));
+        let node_ids = vec!["1", "2", "3", "4", "5", "6"];
+
+        for from in &node_ids {
+            for to in &node_ids {
+                let (expected, _) = dijkstra(&graph, from, Some(to));
+                let (actual, _) = multi_region_shortest_path(&graph, &arc_flags, from, to);
+                assert_eq!(actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn partition_queries_match_dijkstra() {
+        let graph = build_graph();
+        let partition = inertial_flow_partition(&graph, 3);
+        let arc_flags = assign_partition_arc_flags(&graph, &partition);
+        let node_ids = vec!["1", "2", "3", "4", "5", "6"];
+
+        assert_eq!(arc_flags.region_count(), 3);
+        for from in &node_ids {
+            for to in &node_ids {
+                let (expected, _) = dijkstra(&graph, from, Some(to));
+                let (actual, _) = multi_region_shortest_path(&graph, &arc_flags, from, to);
+                assert_eq!(actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn one_way_arc_flags_match_dijkstra() {
+        let mut graph = build_one_way_graph();
+        let region = Rect { x_min: 1.5,
+                            x_max: 3.5,
+                            y_min: 1.5,
+                            y_max: 3.5
+                          };
+        let node_ids = vec!["1", "2", "3", "4", "5", "6"];
+        let internal: Vec<&str> = graph.all_nodes()
+                                       .iter()
+                                       .filter(|node| region.contains(node))
+                                       .map(|node| node.id)
+                                       .collect();
+
+        assign_arc_flags(&mut graph, region);
+
+        for from in &node_ids {
+            for to in &internal {
+                let (expected, _) = dijkstra(&graph, from, Some(to));
+                let (actual, _) = shortest_path(&graph, from, Some(to));
+                assert_eq!(actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn one_way_multi_region_queries_match_dijkstra() {
+        let graph = build_one_way_graph();
+        let node_ids = vec!["1", "2", "3", "4", "5", "6"];
+
+        for regions in vec![grid_regions(&graph, 2, 2), grid_regions(&graph, 3, 3)] {
+            let arc_flags = assign_region_arc_flags(&graph, regions);
+            for from in &node_ids {
+                for to in &node_ids {
+                    let (expected, _) = dijkstra(&graph, from, Some(to));
+                    let (actual, _) = multi_region_shortest_path(&graph, &arc_flags, from, to);
+                    assert_eq!(actual, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn polygon_contains_nodes() {
+        let triangle = Polygon::new(vec![(0.0, 0.0), (4.0, 0.0), (0.0, 4.0)]);
+        let inside = Node { id: "inside", x: 1.0, y: 1.0 };
+        let outside = Node { id: "outside", x: 3.0, y: 3.0 };
+        let beyond = Node { id: "beyond", x: -1.0, y: 1.0 };
+
+        assert!(triangle.contains(&inside));
+        assert!(!triangle.contains(&outside));
+        assert!(!triangle.contains(&beyond));
+    }
+
+    #[test]
+    fn regions_with_the_same_nodes_share_flags() {
+        let rect = Rect { x_min: 1.5,
+                          x_max: 3.5,
+                          y_min: 1.5,
+                          y_max: 3.5
+                        };
+        let polygon = Polygon::new(vec![(1.5, 1.5), (3.5, 2.5), (3.5, 3.5), (1.5, 2.5)]);
+        let node_set = NodeSet::new(vec!["3", "4"].into_iter().collect());
+
+        let flags = |graph: &Graph<&'static str>| {
+            graph.all_nodes()
+                 .iter()
+                 .flat_map(|node| graph.get_edges(&node.id).iter())
+                 .filter(|edge| edge.arc_flag)
+                 .map(|edge| edge.id)
+                 .collect::<HashSet<&str>>()
+        };
+        let mut by_rect = build_graph();
+        let mut by_polygon = build_graph();
+        let mut by_node_set = build_graph();
+        assign_arc_flags(&mut by_rect, rect);
+        assign_arc_flags(&mut by_polygon, polygon);
+        assign_arc_flags(&mut by_node_set, node_set);
+
+        assert_eq!(flags(&by_polygon), flags(&by_rect));
+        assert_eq!(flags(&by_node_set), flags(&by_rect));
+        assert!(boundary_node(&by_node_set, &NodeSet::new(vec!["4"].into_iter().collect()), &"4"));
+    }
+
+    #[test]
+    fn cell_region_queries_match_dijkstra() {
+        let mut graph = build_one_way_graph();
+        let partition = inertial_flow_partition(&graph, 2);
+        let region = NodeSet::from_cell(&partition, 0);
+        let internal = partition.nodes_in_cell(0);
+        let node_ids = vec!["1", "2", "3", "4", "5", "6"];
+
+        assign_arc_flags(&mut graph, region);
+
+        for from in &node_ids {
+            for to in &internal {
+                let (expected, _) = dijkstra(&graph, from, Some(to));
+                let (actual, _) = shortest_path(&graph, from, Some(to));
+                assert_eq!(actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn maintained_flags_match_assigned_flags() {
+        let mut graph = build_graph();
+        let mut expected = build_graph();
+        let region = Rect { x_min: 1.5,
+                            x_max: 3.5,
+                            y_min: 1.5,
+                            y_max: 3.5
+                          };
+
+        let trees = assign_maintained_arc_flags(&mut graph, &region);
+        assign_arc_flags(&mut expected, region);
+
+        assert_eq!(trees.boundary_nodes(), vec!["4"]);
+        for node in graph.all_nodes() {
+            for edge in graph.get_edges(&node.id) {
+                assert_eq!(edge.arc_flag, expected.get_mut_edge(&edge.from_id, &edge.to_id).unwrap().arc_flag);
+            }
+        }
+    }
+
+    #[test]
+    fn only_affected_trees_are_searched_again() {
+        let mut graph = build_one_way_graph();
+        let region = NodeSet::new(vec!["3", "4", "5"].into_iter().collect());
+        let node_ids = vec!["1", "2", "3", "4", "5", "6"];
+
+        let mut trees = assign_maintained_arc_flags(&mut graph, &region);
+        let mut boundary_nodes = trees.boundary_nodes();
+        boundary_nodes.sort();
+        assert_eq!(boundary_nodes, vec!["4", "5"]);
+
+        // 2 -> 6 is in no tree, and making it longer cannot shorten any path
+        assert!(trees.update_edge_weights(&mut graph, &[("2", "6", 9)]).is_empty());
+        let mut searched = trees.update_edge_weights(&mut graph, &[("6", "4", 1)]);
+        searched.sort();
+        assert_eq!(searched, vec!["4", "5"]);
+        // 6 now leads straight to 4, so 6 -> 2 has left both trees
+        assert!(trees.update_edge_weights(&mut graph, &[("6", "2", 9)]).is_empty());
+        assert_eq!(trees.update_edge_weights(&mut graph, &[("4", "5", 6), ("x", "5", 1)]), vec!["5"]);
+
+        for from in &node_ids {
+            for to in &["3", "4", "5"] {
+                let (expected, _) = dijkstra(&graph, from, Some(to));
+                let (actual, _) = shortest_path(&graph, from, Some(to));
+                assert_eq!(actual, expected);
+            }
+        }
+    }
 }
@@ -1,282 +1,727 @@
-use std::collections::{ BinaryHeap, HashMap };
+use std::collections::{ BinaryHeap, HashMap, HashSet };
+use std::collections::hash_map::DefaultHasher;
 use std::cmp::Ordering;
+use std::hash::Hasher;
+use std::thread;
 
-use weighted_graph::{ GraphKey, Graph, Node, Edge };
-use arc_flags::shortest_path as arc_flags_shortest_path;
-use pathfinder::{ CurrentBest, Pathfinder, EdgeIterator };
+use weighted_graph::{ GraphKey, Graph };
+use pathfinder::CurrentBest;
+use contraction_hierarchy::{ ContractionHierarchy, HierarchyEdge };
 
-pub fn shortest_path<T>(graph: &Graph<T>,
+pub fn shortest_path<T>(hierarchy: &ContractionHierarchy<T>,
                         source: &T,
                         destination: &T
                         ) -> Option<(i64, Vec<T>)>
        where T: GraphKey {
-    let (_, from_source) = arc_flags_shortest_path(graph, source, None);
-    let (_, from_dest) = arc_flags_shortest_path(graph, destination, None);
-
-    match from_source.iter()
-                     .filter_map(|(node_id, source_result)|
-                                  from_dest.get(node_id)
-                                           .map(|dest_result|
-                                                (dest_result.cost + source_result.cost, node_id.clone())
-                                                )
-                                           ).min_by_key(|&(cost, _)| cost) {
-        Some((cost, joint)) => {
-            let mut back_path = backtrack_path(graph, &joint, &from_source);
-            let mut fore_path = backtrack_path(graph, &joint, &from_dest);
-            back_path.reverse();
-            back_path.pop();
-            back_path.append(&mut fore_path);
-            Some((cost, back_path))
-        },
-        None => None
-    }
-}
-
-pub fn preprocess_contraction<T>(graph: &mut Graph<T>)
+    let mut forward = UpwardSearch::new(source, SearchDirection::Forward);
+    let mut backward = UpwardSearch::new(destination, SearchDirection::Backward);
+    let mut best: Option<(i64, T)> = None;
+
+    loop {
+        let best_cost = best.as_ref().map_or(i64::max_value(), |&(cost, _)| cost);
+        let forward_min = forward.min_cost().filter(|&cost| cost < best_cost);
+        let backward_min = backward.min_cost().filter(|&cost| cost < best_cost);
+
+        let meeting = match (forward_min, backward_min) {
+            (Some(f), Some(b)) if f <= b => forward.settle_next(hierarchy, &backward),
+            (Some(_), None) => forward.settle_next(hierarchy, &backward),
+            (_, Some(_)) => backward.settle_next(hierarchy, &forward),
+            (None, None) => break
+        };
+
+        if let Some((cost, node_id)) = meeting {
+            if cost < best_cost {
+                best = Some((cost, node_id));
+            }
+        }
+    }
+
+    best.map(|(cost, joint)| {
+        let mut hierarchy_path = backtrack_path(&joint, &forward.results);
+        hierarchy_path.reverse();
+        hierarchy_path.pop();
+        hierarchy_path.append(&mut backtrack_path(&joint, &backward.results));
+        (cost, unpack_path(hierarchy, &hierarchy_path))
+    })
+}
+
+// costs from the origin to every node reachable by going up the hierarchy
+pub fn upward_search<T>(hierarchy: &ContractionHierarchy<T>, origin: &T) -> HashMap<T, CurrentBest<T>>
        where T: GraphKey {
-    let node_order = preorder_nodes(graph);
-    contract_graph(graph, node_order);
-    set_increasing_arc_flags(graph);
+    search_up(hierarchy, origin, SearchDirection::Forward, &|_: &T| false)
 }
 
-fn contract_graph<T>(graph: &mut Graph<T>,
-                     mut order: BinaryHeap<EdgeDifference<T>>)
+// costs from every node that reaches the destination by coming down the hierarchy
+pub fn backward_upward_search<T>(hierarchy: &ContractionHierarchy<T>,
+                                 destination: &T) -> HashMap<T, CurrentBest<T>>
        where T: GraphKey {
-    let mut contraction_order = 0;
+    search_up(hierarchy, destination, SearchDirection::Backward, &|_: &T| false)
+}
 
-    while let Some(next_node) = order.pop() {
-        let contracted = graph.get_node(&next_node.node_id)
-                              .and_then(|n| n.contraction_order)
-                              .is_some();
-        if !contracted {
-            let edge_difference = contract_node(graph, &next_node.node_id, true);
-
-            if edge_difference <= next_node.edge_difference {
-                contraction_order += 1;
-                graph.get_mut_node(&next_node.node_id).map(|n| n.contraction_order = Some(contraction_order));
-                contract_node(graph, &next_node.node_id, false);
+// as upward_search, but nodes for which stop is true are settled without going on past them
+pub fn upward_search_stopping_at<T, F>(hierarchy: &ContractionHierarchy<T>,
+                                       origin: &T,
+                                       stop: &F) -> HashMap<T, CurrentBest<T>>
+       where T: GraphKey,
+             F: Fn(&T) -> bool {
+    search_up(hierarchy, origin, SearchDirection::Forward, stop)
+}
+
+pub fn backward_upward_search_stopping_at<T, F>(hierarchy: &ContractionHierarchy<T>,
+                                                destination: &T,
+                                                stop: &F) -> HashMap<T, CurrentBest<T>>
+       where T: GraphKey,
+             F: Fn(&T) -> bool {
+    search_up(hierarchy, destination, SearchDirection::Backward, stop)
+}
+
+fn search_up<T, F>(hierarchy: &ContractionHierarchy<T>,
+                   origin: &T,
+                   direction: SearchDirection,
+                   stop: &F) -> HashMap<T, CurrentBest<T>>
+   where T: GraphKey,
+         F: Fn(&T) -> bool {
+    let mut search = UpwardSearch::new(origin, direction);
+    // there is no second search to meet, so its results are never used
+    let other = UpwardSearch::new(origin, direction);
+    while search.min_cost().is_some() {
+        search.settle_next_unless(hierarchy, &other, stop);
+    }
+    search.results
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum SearchDirection {
+    Forward,
+    Backward
+}
+
+// one half of the bidirectional query, following only edges to higher nodes
+struct UpwardSearch<T: GraphKey> {
+    direction: SearchDirection,
+    results: HashMap<T, CurrentBest<T>>,
+    min_heap: BinaryHeap<CurrentBest<T>>
+}
+
+impl<T: GraphKey> UpwardSearch<T> {
+    fn new(origin: &T, direction: SearchDirection) -> Self {
+        let initial = CurrentBest { id: origin.clone(),
+                                    cost: 0,
+                                    predecessor: None
+                                  };
+        let mut results = HashMap::new();
+        let mut min_heap = BinaryHeap::new();
+        results.insert(origin.clone(), initial.clone());
+        min_heap.push(initial);
+        UpwardSearch { direction: direction, results: results, min_heap: min_heap }
+    }
+
+    fn min_cost(&mut self) -> Option<i64> {
+        while let Some(stale) = self.min_heap.peek().map(|top| self.is_stale(top)) {
+            if stale {
+                self.min_heap.pop();
             } else {
-                order.push(EdgeDifference { node_id: next_node.node_id,
-                                            edge_difference: edge_difference
-                                          });
+                break;
             }
         }
+        self.min_heap.peek().map(|top| top.cost)
+    }
+
+    fn is_stale(&self, entry: &CurrentBest<T>) -> bool {
+        self.results.get(&entry.id).map_or(true, |best| best.cost < entry.cost)
+    }
+
+    // edges the search follows up from a node, and edges coming back down into it
+    fn edges<'a>(&self,
+                 hierarchy: &'a ContractionHierarchy<T>,
+                 node_id: &T) -> (&'a [HierarchyEdge<T>], &'a [HierarchyEdge<T>]) {
+        match self.direction {
+            SearchDirection::Forward => (hierarchy.upward_edges(node_id),
+                                         hierarchy.downward_edges(node_id)),
+            SearchDirection::Backward => (hierarchy.downward_edges(node_id),
+                                          hierarchy.upward_edges(node_id))
+        }
+    }
+
+    // returns the cost of the path through the settled node if the other search has reached it
+    fn settle_next(&mut self,
+                   hierarchy: &ContractionHierarchy<T>,
+                   other: &UpwardSearch<T>) -> Option<(i64, T)> {
+        self.settle_next_unless(hierarchy, other, &|_: &T| false)
+    }
+
+    // the settled node's edges are left alone if stop is true for it
+    fn settle_next_unless<F>(&mut self,
+                             hierarchy: &ContractionHierarchy<T>,
+                             other: &UpwardSearch<T>,
+                             stop: &F) -> Option<(i64, T)>
+       where F: Fn(&T) -> bool {
+        let current = match self.min_heap.pop() {
+            Some(current) => current,
+            None => return None
+        };
+        let meeting = other.results.get(&current.id).map(|reached|
+            (current.cost + reached.cost, current.id.clone())
+        );
+
+        let (up_edges, _) = self.edges(hierarchy, &current.id);
+        if !stop(&current.id) && !self.is_stalled(hierarchy, &current) {
+            for edge in up_edges {
+                let next_id = other_end(edge, &current.id);
+                let cost = current.cost + edge.weight;
+                let node_cost = self.results.get(next_id)
+                                            .map_or(i64::max_value(), |node| node.cost);
+                if cost < node_cost {
+                    let hnode = CurrentBest { id: next_id.clone(),
+                                              cost: cost,
+                                              predecessor: Some(current.id.clone())
+                                            };
+                    self.min_heap.push(hnode.clone());
+                    self.results.insert(next_id.clone(), hnode);
+                }
+            }
+        }
+        meeting
+    }
+
+    // a node reached more cheaply from a higher node cannot be on a shortest up-down path
+    fn is_stalled(&self, hierarchy: &ContractionHierarchy<T>, current: &CurrentBest<T>) -> bool {
+        let (_, down_edges) = self.edges(hierarchy, &current.id);
+        down_edges.iter().any(|edge|
+            self.results
+                .get(other_end(edge, &current.id))
+                .map_or(false, |higher| higher.cost + edge.weight < current.cost)
+        )
     }
 }
 
-fn set_increasing_arc_flags<T>(graph: &mut Graph<T>)
+fn other_end<'a, T>(edge: &'a HierarchyEdge<T>, node_id: &T) -> &'a T
    where T: GraphKey {
-    let node_ids: Vec<T> = graph.all_nodes()
-                                .iter()
-                                .map(|node| node.id.clone())
-                                .collect();
-    for id in node_ids {
-        let current_order = graph.get_node(&id).and_then(|n| n.contraction_order).unwrap();
-        let connected_node_ids: Vec<T> = graph.get_edges(&id)
-                                                .iter()
-                                                .map(|e| e.to_id.clone())
-                                                .collect();
-        for cid in connected_node_ids {
-            if graph.get_node(&cid).and_then(|n| n.contraction_order).unwrap() > current_order {
-                graph.get_mut_edge(&id, &cid).map(|e| e.arc_flag = true);
+    if edge.from_id == *node_id {
+        &edge.to_id
+    } else {
+        &edge.from_id
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ContractionConfig {
+    // witness searches give up after reaching this many nodes;
+    // ordering only simulates contractions, so it can afford to be less thorough
+    pub ordering_witness_limit: usize,
+    pub contraction_witness_limit: usize,
+    pub edge_difference_weight: i64,
+    pub contracted_neighbors_weight: i64,
+    pub original_edges_weight: i64,
+    pub level_weight: i64
+}
+
+impl Default for ContractionConfig {
+    fn default() -> Self {
+        ContractionConfig { ordering_witness_limit: 20,
+                            contraction_witness_limit: 100,
+                            edge_difference_weight: 2,
+                            contracted_neighbors_weight: 1,
+                            original_edges_weight: 1,
+                            level_weight: 1
+                          }
+    }
+}
+
+pub fn preprocess_contraction<T>(graph: &Graph<T>) -> ContractionHierarchy<T>
+       where T: GraphKey {
+    preprocess_contraction_with_config(graph, &ContractionConfig::default())
+}
+
+pub fn preprocess_contraction_with_config<T>(graph: &Graph<T>,
+                                             config: &ContractionConfig) -> ContractionHierarchy<T>
+       where T: GraphKey {
+    let mut remaining = RemainingGraph::from_graph(graph);
+    let mut state = ContractionState::new();
+    let node_order = preorder_nodes(&remaining, &state, config);
+    let (orders, edges) = contract_graph(&mut remaining, &mut state, node_order, config);
+    ContractionHierarchy::new(orders, edges)
+}
+
+// contracts rounds of nodes with locally minimal priority, no two of them adjacent,
+// computing their shortcuts and the updated priorities on several threads
+pub fn preprocess_contraction_in_parallel<T>(graph: &Graph<T>,
+                                             config: &ContractionConfig,
+                                             threads: usize) -> ContractionHierarchy<T>
+       where T: GraphKey + Send + Sync {
+    let mut remaining = RemainingGraph::from_graph(graph);
+    let mut state = ContractionState::new();
+    let mut orders = HashMap::new();
+    let mut edges = vec![];
+
+    let node_ids = remaining.node_ids();
+    let initial = parallel_map(&node_ids, threads, |node_id|
+        node_priority(&remaining, &state, node_id, config)
+    );
+    let mut priorities: HashMap<T, i64> = node_ids.into_iter().zip(initial).collect();
+
+    while !priorities.is_empty() {
+        let independent = independent_nodes(&remaining, &priorities);
+        // witnesses must not pass through nodes contracted in the same round
+        let contracting: HashSet<T> = independent.iter().cloned().collect();
+        let all_shortcuts = parallel_map(&independent, threads, |node_id|
+            shortcuts_avoiding(&remaining, node_id, &contracting, config.contraction_witness_limit)
+        );
+
+        let mut affected = HashSet::new();
+        for (node_id, shortcuts) in independent.iter().zip(all_shortcuts) {
+            let contraction_order = orders.len() as i64 + 1;
+            orders.insert(node_id.clone(), contraction_order);
+            let adjacent_nodes = remaining.neighbors(node_id);
+            edges.append(&mut remaining.remove_node(node_id));
+            for shortcut in &shortcuts {
+                remaining.add_shortcut(shortcut, node_id);
             }
+            state.record_contraction(node_id, &adjacent_nodes, &shortcuts);
+            priorities.remove(node_id);
+            affected.extend(adjacent_nodes);
         }
+
+        let affected: Vec<T> = affected.into_iter().collect();
+        let updated = parallel_map(&affected, threads, |node_id|
+            node_priority(&remaining, &state, node_id, config)
+        );
+        priorities.extend(affected.into_iter().zip(updated));
     }
+    ContractionHierarchy::new(orders, edges)
 }
 
-fn local_shortest_path<'a, T>(graph: &'a Graph<T>,
-                              source: &T,
-                              destination: &T,
-                              max_nodes: usize,
-                              max_cost: i64
-                             ) -> (i64, HashMap<T, CurrentBest<T>>)
-    where T: GraphKey {
-    let identity = |_: Option<&Node<T>>, _ :Option<&Node<T>>| 0;
-    let edge_iterator = |g: &'a Graph<T>, node_id: &T| ->
-                        EdgeIterator<'a, T> {
-        Box::new(g.get_edges(node_id).iter().filter(|edge| edge.arc_flag))
-    };
-    let terminator = move |r: &CurrentBest<T>, rs: &HashMap<T, CurrentBest<T>>| {
-        rs.len() >= max_nodes || r.cost > max_cost
+// nodes whose priority is lower than all of their neighbors', ties broken by a hash of the id
+fn independent_nodes<T>(remaining: &RemainingGraph<T>, priorities: &HashMap<T, i64>) -> Vec<T>
+   where T: GraphKey {
+    let key = |node_id: &T| {
+        let mut hasher = DefaultHasher::new();
+        node_id.hash(&mut hasher);
+        (priorities.get(node_id).cloned().unwrap_or(i64::max_value()), hasher.finish())
     };
-    let pathfinder = Pathfinder::new(Box::new(identity),
-                                     Box::new(edge_iterator),
-                                     Box::new(terminator)
-                                    );
-    pathfinder.shortest_path(graph, source, Some(destination))
+    let mut independent: Vec<(i64, u64, T)> = priorities.keys()
+                                                        .filter(|node_id| {
+                                                            let own = key(node_id);
+                                                            remaining.neighbors(node_id)
+                                                                     .iter()
+                                                                     .all(|neighbor| own < key(neighbor))
+                                                        })
+                                                        .map(|node_id| {
+                                                            let (priority, hash) = key(node_id);
+                                                            (priority, hash, node_id.clone())
+                                                        })
+                                                        .collect();
+    independent.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+    independent.into_iter().map(|(_, _, node_id)| node_id).collect()
 }
 
-fn contract_node<T>(graph: &mut Graph<T>, node_id: &T, count_only: bool) -> i64
-   where T: GraphKey {
-    let adjacent_nodes = find_adjacent_nodes(graph, node_id);
-    // assuming the graph is symmetric and directed
-    // edges = 2 * adjacent nodes
-    let mut ed: i64 = adjacent_nodes.len() as i64 * 2 * -1;
+// f applied to every item, the items split into one chunk per thread; results keep the items' order
+pub fn parallel_map<I, R, F>(items: &[I], threads: usize, f: F) -> Vec<R>
+   where I: Sync,
+         R: Send,
+         F: Fn(&I) -> R + Sync {
+    let threads = threads.max(1);
+    let chunk_size = ((items.len() + threads - 1) / threads).max(1);
+    let f = &f;
+    thread::scope(|scope| {
+        let handles: Vec<_> = items.chunks(chunk_size)
+                                   .map(|chunk| scope.spawn(move || chunk.iter().map(f).collect::<Vec<R>>()))
+                                   .collect();
+        handles.into_iter()
+               .flat_map(|handle| handle.join().unwrap())
+               .collect()
+    })
+}
+
+// the nodes that have not been contracted yet, along with the shortcuts between them
+struct RemainingGraph<T: GraphKey> {
+    edges: HashMap<T, HashMap<T, HierarchyEdge<T>>>,
+    // nodes with an edge into each node
+    incoming: HashMap<T, HashSet<T>>
+}
+
+impl<T: GraphKey> RemainingGraph<T> {
+    fn from_graph(graph: &Graph<T>) -> Self {
+        let mut remaining = RemainingGraph { edges: HashMap::new(), incoming: HashMap::new() };
+        for node in graph.all_nodes() {
+            remaining.edges.insert(node.id.clone(), HashMap::new());
+            remaining.incoming.insert(node.id.clone(), HashSet::new());
+        }
+        for node in graph.all_nodes() {
+            for edge in graph.get_edges(&node.id).iter().filter(|edge| edge.to_id != edge.from_id) {
+                // parallel edges collapse into the lightest one
+                if remaining.edge_weight(&edge.from_id, &edge.to_id).map_or(true, |existing| edge.weight < existing) {
+                    remaining.insert_edge(HierarchyEdge { id: edge.id.clone(),
+                                                          from_id: edge.from_id.clone(),
+                                                          to_id: edge.to_id.clone(),
+                                                          weight: edge.weight,
+                                                          shortcut: None
+                                                        });
+                }
+            }
+        }
+        remaining
+    }
 
-    for adjacent in &adjacent_nodes {
-        remove_from_graph(graph, adjacent, node_id);
+    fn insert_edge(&mut self, edge: HierarchyEdge<T>) {
+        self.incoming.entry(edge.to_id.clone()).or_insert(HashSet::new()).insert(edge.from_id.clone());
+        self.edges.entry(edge.from_id.clone()).or_insert(HashMap::new()).insert(edge.to_id.clone(), edge);
     }
 
-    for from_node in &adjacent_nodes {
-        for to_node in &adjacent_nodes {
-            let weight_across = weight_across_node(graph,
-                                                   from_node,
-                                                   to_node,
-                                                   node_id
-                                                  );
-            let (min_weight, _) = local_shortest_path(graph,
-                                                      from_node,
-                                                      to_node,
-                                                      20,
-                                                      weight_across);
-
-            if min_weight > weight_across {
-                ed += 1;
-                if !count_only {
-                    add_shortcut(graph, from_node, to_node, node_id, weight_across);
+    fn contains(&self, node_id: &T) -> bool {
+        self.edges.contains_key(node_id)
+    }
+
+    fn node_ids(&self) -> Vec<T> {
+        self.edges.keys().cloned().collect()
+    }
+
+    fn in_neighbors(&self, node_id: &T) -> Vec<T> {
+        self.incoming.get(node_id).map_or(vec![], |incoming| incoming.iter().cloned().collect())
+    }
+
+    fn out_neighbors(&self, node_id: &T) -> Vec<T> {
+        self.edges.get(node_id).map_or(vec![], |outgoing| outgoing.keys().cloned().collect())
+    }
+
+    // nodes connected to the node in either direction
+    fn neighbors(&self, node_id: &T) -> Vec<T> {
+        let mut neighbors = self.out_neighbors(node_id);
+        for in_neighbor in self.in_neighbors(node_id) {
+            if !neighbors.contains(&in_neighbor) {
+                neighbors.push(in_neighbor);
+            }
+        }
+        neighbors
+    }
+
+    fn edge_weight(&self, from_id: &T, to_id: &T) -> Option<i64> {
+        self.edges.get(from_id).and_then(|outgoing| outgoing.get(to_id)).map(|edge| edge.weight)
+    }
+
+    // takes the node and every edge touching it out of the remaining graph
+    fn remove_node(&mut self, node_id: &T) -> Vec<HierarchyEdge<T>> {
+        let mut removed = vec![];
+        for in_neighbor in self.incoming.remove(node_id).unwrap_or(HashSet::new()) {
+            if let Some(edge) = self.edges.get_mut(&in_neighbor).and_then(|outgoing| outgoing.remove(node_id)) {
+                removed.push(edge);
+            }
+        }
+        for (out_neighbor, edge) in self.edges.remove(node_id).unwrap_or(HashMap::new()) {
+            self.incoming.get_mut(&out_neighbor).map(|incoming| incoming.remove(node_id));
+            removed.push(edge);
+        }
+        removed
+    }
+
+    fn add_shortcut(&mut self, shortcut: &Shortcut<T>, node_id: &T) {
+        // replace a heavier existing edge instead of adding a parallel one
+        if self.edge_weight(&shortcut.from_id, &shortcut.to_id)
+               .map_or(false, |existing| existing <= shortcut.weight) {
+            return;
+        }
+        self.insert_edge(HierarchyEdge { id: shortcut.from_id.clone(),
+                                         from_id: shortcut.from_id.clone(),
+                                         to_id: shortcut.to_id.clone(),
+                                         weight: shortcut.weight,
+                                         shortcut: Some(node_id.clone())
+                                       });
+    }
+
+    // cost of the shortest path that avoids the given nodes, if one is found before the
+    // search settles `max_nodes` nodes or exceeds `max_cost`
+    fn witness_cost(&self,
+                    source: &T,
+                    destination: &T,
+                    avoid: &HashSet<T>,
+                    max_nodes: usize,
+                    max_cost: i64) -> Option<i64> {
+        let mut results: HashMap<T, i64> = HashMap::new();
+        let mut min_heap = BinaryHeap::new();
+        let mut settled = 0;
+        results.insert(source.clone(), 0);
+        min_heap.push(CurrentBest { id: source.clone(), cost: 0, predecessor: None });
+
+        while let Some(current) = min_heap.pop() {
+            if results.get(&current.id).map_or(false, |&cost| cost < current.cost) {
+                continue;
+            }
+            if current.id == *destination {
+                return Some(current.cost)
+            }
+            settled += 1;
+            if settled >= max_nodes || current.cost > max_cost {
+                break;
+            }
+
+            let outgoing = self.edges.get(&current.id).map(|outgoing| outgoing.values());
+            for edge in outgoing.into_iter().flat_map(|edges| edges) {
+                if avoid.contains(&edge.to_id) {
+                    continue;
+                }
+                let cost = current.cost + edge.weight;
+                if results.get(&edge.to_id).map_or(true, |&known| cost < known) {
+                    results.insert(edge.to_id.clone(), cost);
+                    min_heap.push(CurrentBest { id: edge.to_id.clone(),
+                                                cost: cost,
+                                                predecessor: Some(current.id.clone())
+                                              });
                 }
             }
         }
+        // any path the search has found is a witness, even if it was cut short
+        results.get(destination).cloned()
+    }
+}
+
+struct ContractionState<T: GraphKey> {
+    contracted_neighbors: HashMap<T, i64>,
+    levels: HashMap<T, i64>,
+    original_edges: HashMap<(T, T), i64>
+}
+
+impl<T: GraphKey> ContractionState<T> {
+    fn new() -> Self {
+        ContractionState { contracted_neighbors: HashMap::new(),
+                           levels: HashMap::new(),
+                           original_edges: HashMap::new()
+                         }
+    }
+
+    fn contracted_neighbors(&self, node_id: &T) -> i64 {
+        self.contracted_neighbors.get(node_id).cloned().unwrap_or(0)
+    }
+
+    fn level(&self, node_id: &T) -> i64 {
+        self.levels.get(node_id).cloned().unwrap_or(0)
+    }
+
+    // number of edges of the original graph an edge stands for
+    fn original_edges(&self, from_id: &T, to_id: &T) -> i64 {
+        self.original_edges.get(&(from_id.clone(), to_id.clone())).cloned().unwrap_or(1)
+    }
+
+    fn shortcut_original_edges(&self, node_id: &T, shortcut: &Shortcut<T>) -> i64 {
+        self.original_edges(&shortcut.from_id, node_id) + self.original_edges(node_id, &shortcut.to_id)
     }
-    if count_only {
-        for adjacent in &adjacent_nodes {
-            unremove_from_graph(graph, adjacent, node_id);
+
+    fn record_contraction(&mut self, node_id: &T, adjacent_nodes: &[T], shortcuts: &[Shortcut<T>]) {
+        let level = self.level(node_id) + 1;
+        for adjacent in adjacent_nodes {
+            *self.contracted_neighbors.entry(adjacent.clone()).or_insert(0) += 1;
+            let adjacent_level = self.levels.entry(adjacent.clone()).or_insert(0);
+            if *adjacent_level < level {
+                *adjacent_level = level;
+            }
+        }
+        for shortcut in shortcuts {
+            let original_edges = self.shortcut_original_edges(node_id, shortcut);
+            self.original_edges.insert((shortcut.from_id.clone(), shortcut.to_id.clone()),
+                                       original_edges);
         }
     }
-    ed
 }
 
-fn find_adjacent_nodes<T>(graph: &Graph<T>, node_id: &T) -> Vec<T>
-    // assuming the graph is symmetric and directed
-    // adjacent nodes <=> nodes on outgoing edges
+fn node_priority<T>(remaining: &RemainingGraph<T>,
+                    state: &ContractionState<T>,
+                    node_id: &T,
+                    config: &ContractionConfig) -> i64
    where T: GraphKey {
-    graph.get_edges(node_id)
-         .iter()
-         .filter(|edge| edge.arc_flag)
-         .map(|edge| edge.to_id.clone())
-         .collect()
+    let shortcuts = contract_node(remaining, node_id, config.ordering_witness_limit);
+    let original_edges: i64 = shortcuts.iter()
+                                       .map(|shortcut| state.shortcut_original_edges(node_id, shortcut))
+                                       .sum();
+
+    config.edge_difference_weight * edge_difference(remaining, node_id, &shortcuts) +
+        config.contracted_neighbors_weight * state.contracted_neighbors(node_id) +
+        config.original_edges_weight * original_edges +
+        config.level_weight * state.level(node_id)
 }
 
-fn remove_from_graph<T>(graph: &mut Graph<T>, adjacent_id: &T, node_id: &T)
-   where T: GraphKey {
-    change_arc_flag(graph, adjacent_id, node_id, false);
+fn contract_graph<T>(remaining: &mut RemainingGraph<T>,
+                     state: &mut ContractionState<T>,
+                     mut order: BinaryHeap<Priority<T>>,
+                     config: &ContractionConfig) -> (HashMap<T, i64>, Vec<HierarchyEdge<T>>)
+       where T: GraphKey {
+    let mut orders = HashMap::new();
+    let mut edges = vec![];
+
+    while let Some(next_node) = order.pop() {
+        if remaining.contains(&next_node.node_id) {
+            let priority = node_priority(remaining, state, &next_node.node_id, config);
+
+            if order.peek().map_or(true, |top| priority <= top.priority) {
+                let contraction_order = orders.len() as i64 + 1;
+                orders.insert(next_node.node_id.clone(), contraction_order);
+                let adjacent_nodes = remaining.neighbors(&next_node.node_id);
+                let (mut node_edges, shortcuts) = contract(remaining,
+                                                           &next_node.node_id,
+                                                           config.contraction_witness_limit);
+                edges.append(&mut node_edges);
+                state.record_contraction(&next_node.node_id, &adjacent_nodes, &shortcuts);
+
+                // only the neighbors' priorities can have changed
+                for adjacent in adjacent_nodes {
+                    let priority = node_priority(remaining, state, &adjacent, config);
+                    order.push(Priority { node_id: adjacent,
+                                          priority: priority
+                                        });
+                }
+            } else {
+                order.push(Priority { node_id: next_node.node_id,
+                                      priority: priority
+                                    });
+            }
+        }
+    }
+    (orders, edges)
 }
 
-fn unremove_from_graph<T>(graph: &mut Graph<T>, adjacent_id: &T, node_id: &T)
-   where T: GraphKey {
-    change_arc_flag(graph, adjacent_id, node_id, true);
+#[derive(Clone, PartialEq, Debug)]
+struct Shortcut<T: GraphKey> {
+    from_id: T,
+    to_id: T,
+    weight: i64
 }
 
-fn change_arc_flag<T>(graph: &mut Graph<T>, adjacent_id: &T, node_id: &T, flag: bool)
+// the shortcuts contracting the node would need, without contracting it
+fn contract_node<T>(remaining: &RemainingGraph<T>,
+                    node_id: &T,
+                    witness_limit: usize) -> Vec<Shortcut<T>>
    where T: GraphKey {
-    graph.get_mut_edge(node_id, adjacent_id)
-        .map(|edge| edge.arc_flag = flag);
-    graph.get_mut_edge(adjacent_id, node_id)
-            .map(|edge| edge.arc_flag = flag);
+    let avoid = vec![node_id.clone()].into_iter().collect();
+    shortcuts_avoiding(remaining, node_id, &avoid, witness_limit)
 }
 
-fn weight_across_node<T>(graph: &Graph<T>,
-                         from_node: &T,
-                         to_node: &T,
-                         cur_node: &T) -> i64
+fn shortcuts_avoiding<T>(remaining: &RemainingGraph<T>,
+                         node_id: &T,
+                         avoid: &HashSet<T>,
+                         witness_limit: usize) -> Vec<Shortcut<T>>
    where T: GraphKey {
-    edge_weight(graph, from_node, cur_node) + edge_weight(graph, cur_node, to_node)
+    let in_neighbors = remaining.in_neighbors(node_id);
+    let out_neighbors = remaining.out_neighbors(node_id);
+    let mut shortcuts = vec![];
+
+    for from_node in &in_neighbors {
+        for to_node in out_neighbors.iter().filter(|to_node| *to_node != from_node) {
+            let weight_across = match (remaining.edge_weight(from_node, node_id),
+                                       remaining.edge_weight(node_id, to_node)) {
+                (Some(first), Some(second)) => first + second,
+                _ => continue
+            };
+            let witnessed = remaining.witness_cost(from_node,
+                                                   to_node,
+                                                   avoid,
+                                                   witness_limit,
+                                                   weight_across)
+                                     .map_or(false, |witness| witness <= weight_across);
+
+            if !witnessed {
+                shortcuts.push(Shortcut { from_id: from_node.clone(),
+                                          to_id: to_node.clone(),
+                                          weight: weight_across
+                                        });
+            }
+        }
+    }
+    shortcuts
 }
 
-fn edge_weight<T>(graph: &Graph<T>, from_node: &T, to_node: &T) -> i64
+// removes the node from the remaining graph, returning its edges for the hierarchy
+// and the shortcuts added between its neighbors
+fn contract<T>(remaining: &mut RemainingGraph<T>,
+               node_id: &T,
+               witness_limit: usize) -> (Vec<HierarchyEdge<T>>, Vec<Shortcut<T>>)
    where T: GraphKey {
-    graph.get_edges(from_node)
-          .iter()
-          .find(|edge| edge.to_id == *to_node)
-          .map(|edge| edge.weight)
-          .unwrap_or(0)
-}
-
-fn add_shortcut<T>(graph: &mut Graph<T>,
-                   from_node: &T,
-                   to_node: &T,
-                   shortcut: &T,
-                   weight: i64)
+    let shortcuts = contract_node(remaining, node_id, witness_limit);
+    let edges = remaining.remove_node(node_id);
+    for shortcut in &shortcuts {
+        remaining.add_shortcut(shortcut, node_id);
+    }
+    (edges, shortcuts)
+}
+
+fn edge_difference<T>(remaining: &RemainingGraph<T>, node_id: &T, shortcuts: &[Shortcut<T>]) -> i64
    where T: GraphKey {
-    graph.add_edge(from_node.clone(),
-                   from_node.clone(),
-                   to_node.clone(),
-                   weight);
-    graph.get_mut_edge(from_node, to_node).map(|edge| edge.arc_flag = true);
-    graph.get_mut_edge(from_node, to_node).map(|edge| edge.shortcut = Some(shortcut.clone()));
+    let removed_edges = remaining.in_neighbors(node_id).len() + remaining.out_neighbors(node_id).len();
+    shortcuts.len() as i64 - removed_edges as i64
 }
 
-fn backtrack_path<T>(graph: &Graph<T>, path_start: &T, results: &HashMap<T, CurrentBest<T>>) -> Vec<T>
+// nodes from path_start back to the search's origin
+fn backtrack_path<T>(path_start: &T, results: &HashMap<T, CurrentBest<T>>) -> Vec<T>
    where T: GraphKey {
-    let mut path = vec![];
-    path.push(path_start.clone());
-
-    if let Some(predecessor) = results.get(path_start)
-                                      .and_then(|cb| cb.clone().predecessor) {
-        expand_shortcut(graph,
-                        &predecessor,
-                        &path_start,
-                        &mut path);
-        let mut shortcuts = backtrack_path(graph, &predecessor, results);
-        path.append(&mut shortcuts);
-        path
-    } else {
-        path
+    let mut path = vec![path_start.clone()];
+    let mut current = path_start.clone();
+    while let Some(predecessor) = results.get(&current).and_then(|cb| cb.predecessor.clone()) {
+        path.push(predecessor.clone());
+        current = predecessor;
+    }
+    path
+}
+
+// replaces every shortcut along a path through the hierarchy with the original nodes it skips
+pub fn unpack_path<T>(hierarchy: &ContractionHierarchy<T>, hierarchy_path: &[T]) -> Vec<T>
+       where T: GraphKey {
+    let mut path = hierarchy_path.iter().take(1).cloned().collect();
+    for pair in hierarchy_path.windows(2) {
+        unpack_shortcut(hierarchy, &pair[0], &pair[1], &mut path);
     }
+    path
 }
 
-fn expand_shortcut<T>(graph: &Graph<T>,
-                      predecessor: &T,
-                      current: &T,
+pub fn unpack_edge<T>(hierarchy: &ContractionHierarchy<T>, from_id: &T, to_id: &T) -> Vec<T>
+       where T: GraphKey {
+    let mut path = vec![from_id.clone()];
+    unpack_shortcut(hierarchy, from_id, to_id, &mut path);
+    path
+}
+
+// pushes the nodes after from_id, up to and including to_id
+fn unpack_shortcut<T>(hierarchy: &ContractionHierarchy<T>,
+                      from_id: &T,
+                      to_id: &T,
                       path: &mut Vec<T>)
    where T: GraphKey {
-    match graph.get_edges(predecessor).iter().find(|e| e.to_id == *current) {
-        Some(&Edge { ref shortcut, .. }) => {
-            match shortcut {
-                &Some(ref shortcut_node) => {
-                    path.push(shortcut_node.clone());
-                    expand_shortcut(graph, &predecessor, &shortcut_node, path);
-                },
-                &None => {}
-            }
-        },
-        None => {}
+    match hierarchy.edge(from_id, to_id).and_then(|edge| edge.shortcut.clone()) {
+        Some(middle) => {
+            unpack_shortcut(hierarchy, from_id, &middle, path);
+            unpack_shortcut(hierarchy, &middle, to_id, path);
+        }
+        None => path.push(to_id.clone())
     }
 }
 
 #[derive(Clone, Eq, PartialEq, Debug)]
-struct EdgeDifference<T: GraphKey> {
+struct Priority<T: GraphKey> {
     node_id: T,
-    edge_difference: i64
+    priority: i64
 }
 
-impl<T> Ord for EdgeDifference<T>
+impl<T> Ord for Priority<T>
         where T: GraphKey {
     // flip order so min-heap instead of max-heap
-    fn cmp(&self, other: &EdgeDifference<T>) -> Ordering {
-        other.edge_difference.cmp(&self.edge_difference)
+    fn cmp(&self, other: &Priority<T>) -> Ordering {
+        other.priority.cmp(&self.priority)
     }
 }
 
-impl<T> PartialOrd for EdgeDifference<T>
+impl<T> PartialOrd for Priority<T>
         where T: GraphKey {
-    fn partial_cmp(&self, other: &EdgeDifference<T>) -> Option<Ordering> {
+    fn partial_cmp(&self, other: &Priority<T>) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
-fn preorder_nodes<T>(graph: &mut Graph<T>) -> BinaryHeap<EdgeDifference<T>>
+fn preorder_nodes<T>(remaining: &RemainingGraph<T>,
+                     state: &ContractionState<T>,
+                     config: &ContractionConfig) -> BinaryHeap<Priority<T>>
    where T: GraphKey {
        let mut preorder = BinaryHeap::new();
-       let node_ids: Vec<T> = graph.all_nodes()
-                                   .iter()
-                                   .map(|node| node.id.clone())
-                                   .collect();
-       for node_id in node_ids {
-           let edge_difference = contract_node(graph, &node_id, true);
-           preorder.push(EdgeDifference { node_id: node_id,
-                                          edge_difference: edge_difference
-                                        });
+       for node_id in remaining.node_ids() {
+           let priority = node_priority(remaining, state, &node_id, config);
+           preorder.push(Priority { node_id: node_id,
+                                    priority: priority
+                                  });
        }
 
        preorder
@@ -284,19 +729,35 @@ fn preorder_nodes<T>(graph: &mut Graph<T>) -> BinaryHeap<EdgeDifference<T>>
 
 #[cfg(test)]
 mod test {
-    use weighted_graph::{ Graph };
-    use arc_flags::shortest_path as arc_flags_shortest_path;
-    use super::{ local_shortest_path,
+    use std::collections::{ HashMap, HashSet };
+    use weighted_graph::{ Graph, GraphKey };
+    use dijkstra::shortest_path as dijkstra;
+    use contraction_hierarchy::{ ContractionHierarchy, HierarchyEdge };
+    use customizable_contraction::{ build_topology, customize };
+    use super::{ RemainingGraph,
+                 contract,
                  contract_node,
                  contract_graph,
+                 edge_difference,
+                 independent_nodes,
+                 node_priority,
+                 parallel_map,
                  preorder_nodes,
-                 set_increasing_arc_flags,
+                 ContractionConfig,
+                 ContractionState,
+                 SearchDirection,
+                 Shortcut,
                  preprocess_contraction,
-                 shortest_path
+                 preprocess_contraction_in_parallel,
+                 preprocess_contraction_with_config,
+                 shortest_path,
+                 unpack_edge,
+                 unpack_path,
+                 upward_search,
+                 UpwardSearch
                };
 
-    #[test]
-    fn local_shortest_path_terminates_early_by_cost() {
+    fn build_line_graph() -> Graph<&'static str> {
         let mut graph = Graph::new();
         graph.add_node("a", 0.0, 0.0);
         graph.add_node("b", 1.0, 1.0);
@@ -305,211 +766,152 @@ mod test {
         graph.add_edge("ab", "a", "b", 2);
         graph.add_edge("bc", "b", "c", 3);
         graph.add_edge("cd", "c", "d", 4);
+        graph
+    }
 
-        for (from, to) in  vec![("a", "b"), ("b", "c"), ("c", "d")] {
-            graph.get_mut_edge(&from, &to).map(|edge| edge.arc_flag = true);
-        }
-
-        let (cost, _) = local_shortest_path(&graph, &"a", &"d", 10, 4);
-        assert_eq!(cost, 5);
+    fn avoiding(node_ids: &[&'static str]) -> HashSet<&'static str> {
+        node_ids.iter().cloned().collect()
     }
 
     #[test]
-    fn local_shortest_path_terminates_early_by_neighborhood() {
-        let mut graph = Graph::new();
-        graph.add_node("a", 0.0, 0.0);
-        graph.add_node("b", 1.0, 1.0);
-        graph.add_node("c", 2.0, 2.0);
-        graph.add_node("d", 3.0, 3.0);
-        graph.add_edge("ab", "a", "b", 2);
-        graph.add_edge("bc", "b", "c", 3);
-        graph.add_edge("cd", "c", "d", 4);
-
-        for (from, to) in  vec![("a", "b"), ("b", "c"), ("c", "d")] {
-            graph.get_mut_edge(&from, &to).map(|edge| edge.arc_flag = true);
-        }
+    fn witness_search_terminates_early_by_cost() {
+        let remaining = RemainingGraph::from_graph(&build_line_graph());
 
-        let (_, results) = local_shortest_path(&graph, &"a", &"d", 2, 10);
-        assert_eq!(results.len(), 2);
+        assert_eq!(remaining.witness_cost(&"a", &"d", &avoiding(&[]), 10, 4), None);
+        assert_eq!(remaining.witness_cost(&"a", &"d", &avoiding(&[]), 10, 9), Some(9));
     }
 
     #[test]
-    fn local_shortest_path_ignores_arc_flags_false() {
-        let mut graph = Graph::new();
-        graph.add_node("a", 0.0, 0.0);
-        graph.add_node("b", 1.0, 1.0);
-        graph.add_node("c", 2.0, 2.0);
-        graph.add_node("d", 3.0, 3.0);
-        graph.add_edge("ab", "a", "b", 2);
-        graph.add_edge("bc", "b", "c", 3);
-        graph.add_edge("cd", "c", "d", 4);
+    fn witness_search_terminates_early_by_neighborhood() {
+        let remaining = RemainingGraph::from_graph(&build_line_graph());
 
-        for (from, to) in  vec![("a", "b"), ("b", "c"), ("c", "d")] {
-            graph.get_mut_edge(&from, &to).map(|edge| edge.arc_flag = true);
-        }
+        assert_eq!(remaining.witness_cost(&"a", &"d", &avoiding(&[]), 2, 10), None);
+        assert_eq!(remaining.witness_cost(&"a", &"d", &avoiding(&[]), 4, 10), Some(9));
+    }
 
-        graph.get_mut_edge(&"c", &"d").map(|edge| edge.arc_flag = false);
+    #[test]
+    fn witness_search_avoids_contracted_node() {
+        let remaining = RemainingGraph::from_graph(&build_line_graph());
 
-        let(_, results) = local_shortest_path(&graph, &"a", &"d", 10, 10);
-        assert_eq!(results.len(), 3)
+        assert_eq!(remaining.witness_cost(&"a", &"d", &avoiding(&["c"]), 10, 10), None);
     }
 
-    #[test]
-    fn contract_node_in_shortest_path() {
+    fn build_square_graph(edges: Vec<(&'static str, &'static str, i64)>) -> Graph<&'static str> {
         let mut graph = Graph::new();
         graph.add_node("a", 0.0, 1.0);
         graph.add_node("b", 1.0, 0.0);
         graph.add_node("c", 2.0, 1.0);
         graph.add_node("d", 1.0, 1.0);
-        let edges = vec![("a", "b", 1),
-                         ("b", "c", 1),
-                         ("c", "d", 3),
-                         ("d", "a", 3)];
         for (n1, n2, w) in edges {
             graph.add_edge(n1, n1, n2, w);
             graph.add_edge(n2, n2, n1, w);
-            graph.get_mut_edge(&n1, &n2).map(|edge| edge.arc_flag = true);
-            graph.get_mut_edge(&n2, &n1).map(|edge| edge.arc_flag = true);
         }
+        graph
+    }
 
-        contract_node(&mut graph, &"b", false);
-
-        let added_ac = graph.get_edges(&"a")
-                            .iter()
-                            .find(|edge| edge.to_id == "c")
-                            .unwrap();
-        let added_ca = graph.get_edges(&"c")
-                            .iter()
-                            .find(|edge| edge.to_id == "a")
-                            .unwrap();
-        assert!(added_ac.arc_flag);
-        assert_eq!(added_ac.shortcut, Some("b"));
-        assert_eq!(added_ac.weight, 2);
-        assert!(added_ca.arc_flag);
-        assert_eq!(added_ca.shortcut, Some("b"));
-        assert_eq!(added_ca.weight, 2);
-
-        for edge in graph.get_edges(&"b") {
-            assert!(!edge.arc_flag);
-        }
-        for edge in graph.get_edges(&"a")
-                         .iter()
-                         .filter(|edge| edge.to_id == "b") {
-            assert!(!edge.arc_flag);
-        }
-        for edge in graph.get_edges(&"c")
-                         .iter()
-                         .filter(|edge| edge.to_id == "b") {
-            assert!(!edge.arc_flag);
-        }
+    #[test]
+    fn contract_node_in_shortest_path() {
+        let graph = build_square_graph(vec![("a", "b", 1),
+                                            ("b", "c", 1),
+                                            ("c", "d", 3),
+                                            ("d", "a", 3)]);
+        let mut remaining = RemainingGraph::from_graph(&graph);
+
+        let (edges, shortcuts) = contract(&mut remaining, &"b", 20);
+
+        assert_eq!(shortcuts.len(), 2);
+        assert_eq!(remaining.edge_weight(&"a", &"c"), Some(2));
+        assert_eq!(remaining.edge_weight(&"c", &"a"), Some(2));
+        assert_eq!(remaining.edges.get(&"a").and_then(|es| es.get(&"c")).and_then(|e| e.shortcut),
+                   Some("b"));
+
+        assert_eq!(edges.len(), 4);
+        assert!(edges.iter().all(|edge| edge.from_id == "b" || edge.to_id == "b"));
+        assert!(!remaining.contains(&"b"));
+        assert_eq!(remaining.edge_weight(&"a", &"b"), None);
+        assert_eq!(remaining.edge_weight(&"c", &"b"), None);
     }
 
     #[test]
     fn calculate_edge_difference_in_shortest_path() {
-        let mut graph = Graph::new();
-        graph.add_node("a", 0.0, 1.0);
-        graph.add_node("b", 1.0, 0.0);
-        graph.add_node("c", 2.0, 1.0);
-        graph.add_node("d", 1.0, 1.0);
-        let edges = vec![("a", "b", 1),
-                         ("b", "c", 1),
-                         ("c", "d", 3),
-                         ("d", "a", 3)];
-        for (n1, n2, w) in edges {
-            graph.add_edge(n1, n1, n2, w);
-            graph.add_edge(n2, n2, n1, w);
-            graph.get_mut_edge(&n1, &n2).map(|edge| edge.arc_flag = true);
-            graph.get_mut_edge(&n2, &n1).map(|edge| edge.arc_flag = true);
-        }
+        let graph = build_square_graph(vec![("a", "b", 1),
+                                            ("b", "c", 1),
+                                            ("c", "d", 3),
+                                            ("d", "a", 3)]);
+        let remaining = RemainingGraph::from_graph(&graph);
 
-        let ed = contract_node(&mut graph, &"b", true);
-        assert_eq!(ed, 2 - 4);
+        let shortcuts = contract_node(&remaining, &"b", 20);
+        assert_eq!(edge_difference(&remaining, &"b", &shortcuts), 2 - 4);
 
-        for edge in graph.get_edges(&"b") {
-            assert!(edge.arc_flag);
-        }
-        for edge in graph.get_edges(&"a") {
-            assert!(edge.arc_flag);
-        }
-        for edge in graph.get_edges(&"c") {
-            assert!(edge.arc_flag);
-        }
+        assert_eq!(remaining.neighbors(&"b").len(), 2);
+        assert_eq!(remaining.edge_weight(&"a", &"c"), None);
     }
 
     #[test]
     fn contract_node_not_in_shortest_path() {
-        let mut graph = Graph::new();
-        graph.add_node("a", 0.0, 1.0);
-        graph.add_node("b", 1.0, 0.0);
-        graph.add_node("c", 2.0, 1.0);
-        graph.add_node("d", 1.0, 1.0);
-        let edges = vec![("a", "b", 2),
-                         ("b", "c", 2),
-                         ("c", "d", 1),
-                         ("d", "a", 1)];
-        for (n1, n2, w) in edges {
-            graph.add_edge(n1, n1, n2, w);
-            graph.add_edge(n2, n2, n1, w);
-            graph.get_mut_edge(&n1, &n2).map(|edge| edge.arc_flag = true);
-            graph.get_mut_edge(&n2, &n1).map(|edge| edge.arc_flag = true);
-        }
-
-        contract_node(&mut graph, &"b", false);
+        let graph = build_square_graph(vec![("a", "b", 2),
+                                            ("b", "c", 2),
+                                            ("c", "d", 1),
+                                            ("d", "a", 1)]);
+        let mut remaining = RemainingGraph::from_graph(&graph);
+
+        let (edges, shortcuts) = contract(&mut remaining, &"b", 20);
+
+        assert_eq!(shortcuts, vec![]);
+        assert_eq!(edges.len(), 4);
+        assert_eq!(remaining.edge_weight(&"a", &"c"), None);
+        assert_eq!(remaining.edge_weight(&"c", &"a"), None);
+        assert!(!remaining.contains(&"b"));
+    }
 
-        let added_ac = graph.get_edges(&"a")
-                            .iter()
-                            .find(|edge| edge.to_id == "c");
-        let added_ca = graph.get_edges(&"c")
-                            .iter()
-                            .find(|edge| edge.to_id == "a");
-        assert_eq!(added_ac, None);
-        assert_eq!(added_ca, None);
+    #[test]
+    fn calculate_edge_difference_not_in_shortest_path() {
+        let graph = build_square_graph(vec![("a", "b", 2),
+                                            ("b", "c", 2),
+                                            ("c", "d", 1),
+                                            ("d", "a", 1)]);
+        let remaining = RemainingGraph::from_graph(&graph);
+
+        let shortcuts = contract_node(&remaining, &"b", 20);
+        assert_eq!(edge_difference(&remaining, &"b", &shortcuts), 0 - 4);
+        assert_eq!(remaining.neighbors(&"b").len(), 2);
+    }
 
-        for edge in graph.get_edges(&"b") {
-            assert!(!edge.arc_flag);
-        }
-        for edge in graph.get_edges(&"a")
-                         .iter()
-                         .filter(|edge| edge.to_id == "b") {
-            assert!(!edge.arc_flag);
-        }
-        for edge in graph.get_edges(&"c")
-                         .iter()
-                         .filter(|edge| edge.to_id == "b") {
-            assert!(!edge.arc_flag);
-        }
+    #[test]
+    fn contract_node_follows_edge_direction() {
+        let mut graph = build_square_graph(vec![("a", "b", 1)]);
+        graph.add_edge("bc", "b", "c", 1);
+        let mut remaining = RemainingGraph::from_graph(&graph);
+
+        assert_eq!(remaining.in_neighbors(&"b"), vec!["a"]);
+        let mut out_neighbors = remaining.out_neighbors(&"b");
+        out_neighbors.sort();
+        assert_eq!(out_neighbors, vec!["a", "c"]);
+
+        let shortcuts = contract_node(&remaining, &"b", 20);
+        assert_eq!(shortcuts, vec![Shortcut { from_id: "a", to_id: "c", weight: 2 }]);
+        assert_eq!(edge_difference(&remaining, &"b", &shortcuts), 1 - 3);
+
+        let (edges, _) = contract(&mut remaining, &"b", 20);
+        assert_eq!(edges.len(), 3);
+        assert_eq!(remaining.edge_weight(&"a", &"c"), Some(2));
+        assert_eq!(remaining.edge_weight(&"c", &"a"), None);
+        assert_eq!(remaining.in_neighbors(&"c"), vec!["a"]);
+        assert!(remaining.in_neighbors(&"a").is_empty());
     }
 
     #[test]
-    fn calculate_edge_difference_not_in_shortest_path() {
-        let mut graph = Graph::new();
-        graph.add_node("a", 0.0, 1.0);
-        graph.add_node("b", 1.0, 0.0);
-        graph.add_node("c", 2.0, 1.0);
-        graph.add_node("d", 1.0, 1.0);
-        let edges = vec![("a", "b", 2),
-                         ("b", "c", 2),
-                         ("c", "d", 1),
-                         ("d", "a", 1)];
-        for (n1, n2, w) in edges {
-            graph.add_edge(n1, n1, n2, w);
-            graph.add_edge(n2, n2, n1, w);
-            graph.get_mut_edge(&n1, &n2).map(|edge| edge.arc_flag = true);
-            graph.get_mut_edge(&n2, &n1).map(|edge| edge.arc_flag = true);
-        }
+    fn shortcuts_never_replace_lighter_edges() {
+        let graph = build_square_graph(vec![("a", "b", 1),
+                                            ("b", "c", 1),
+                                            ("c", "a", 1)]);
+        let mut remaining = RemainingGraph::from_graph(&graph);
 
-        let ed = contract_node(&mut graph, &"b", true);
-        assert_eq!(ed, 0 - 4);
+        remaining.add_shortcut(&Shortcut { from_id: "a", to_id: "c", weight: 2 }, &"b");
+        assert_eq!(remaining.edge_weight(&"a", &"c"), Some(1));
 
-        for edge in graph.get_edges(&"b") {
-            assert!(edge.arc_flag);
-        }
-        for edge in graph.get_edges(&"a") {
-            assert!(edge.arc_flag);
-        }
-        for edge in graph.get_edges(&"c") {
-            assert!(edge.arc_flag);
-        }
+        remaining.add_shortcut(&Shortcut { from_id: "a", to_id: "d", weight: 5 }, &"b");
+        assert_eq!(remaining.edge_weight(&"a", &"d"), Some(5));
     }
 
     fn build_full_graph() -> (Vec<(&'static str, f64, f64)>, // nodes
@@ -545,89 +947,159 @@ mod test {
         for &(n1, n2, w) in &edges {
             graph.add_edge(n1, n1, n2, w);
             graph.add_edge(n2, n2, n1, w);
-            graph.get_mut_edge(&n1, &n2).map(|edge| edge.arc_flag = true);
-            graph.get_mut_edge(&n2, &n1).map(|edge| edge.arc_flag = true);
         }
 
         (nodes, edges, graph)
     }
 
     #[test]
-    fn order_nodes_by_edge_difference() {
-        let (_, _, mut graph) = build_full_graph();
+    fn order_nodes_by_priority() {
+        let (nodes, _, graph) = build_full_graph();
 
-        let mut node_order = preorder_nodes(&mut graph);
+        let mut node_order = preorder_nodes(&RemainingGraph::from_graph(&graph),
+                                            &ContractionState::new(),
+                                            &ContractionConfig::default());
+        let mut current_priority = i64::min_value();
+        let mut count = 0;
+
+        while let Some(next_node) = node_order.pop() {
+            assert!(current_priority <= next_node.priority);
+            current_priority = next_node.priority;
+            count += 1;
+        }
+        assert_eq!(count, nodes.len());
+    }
+
+    #[test]
+    fn order_nodes_by_edge_difference_alone() {
+        let (_, _, graph) = build_full_graph();
+        let config = ContractionConfig { edge_difference_weight: 1,
+                                         contracted_neighbors_weight: 0,
+                                         original_edges_weight: 0,
+                                         level_weight: 0,
+                                         .. ContractionConfig::default()
+                                       };
+
+        let mut node_order = preorder_nodes(&RemainingGraph::from_graph(&graph),
+                                            &ContractionState::new(),
+                                            &config);
         let mut current_edge_difference = i64::min_value();
 
         while let Some(next_node) = node_order.pop() {
-            assert!(current_edge_difference <= next_node.edge_difference);
-            current_edge_difference = next_node.edge_difference;
+            assert!(current_edge_difference <= next_node.priority);
+            current_edge_difference = next_node.priority;
         }
         assert_eq!(current_edge_difference, 0);
     }
 
+    #[test]
+    fn priority_combines_weighted_terms() {
+        let (_, _, graph) = build_full_graph();
+        let remaining = RemainingGraph::from_graph(&graph);
+        let mut state = ContractionState::new();
+        // contracting b adds the shortcut e -> c (and back) made of two original edges
+        let shortcuts = vec![Shortcut { from_id: "c", to_id: "e", weight: 2 },
+                             Shortcut { from_id: "e", to_id: "c", weight: 2 }];
+        state.record_contraction(&"b", &["a", "c", "e"], &shortcuts);
+        state.record_contraction(&"a", &["d"], &[]);
+
+        assert_eq!(state.contracted_neighbors(&"c"), 1);
+        assert_eq!(state.level(&"c"), 1);
+        assert_eq!(state.level(&"d"), 2);
+        assert_eq!(state.original_edges(&"c", &"e"), 2);
+        assert_eq!(state.original_edges(&"c", &"f"), 1);
+
+        let only = |edge_difference, contracted_neighbors, original_edges, level| {
+            ContractionConfig { edge_difference_weight: edge_difference,
+                                contracted_neighbors_weight: contracted_neighbors,
+                                original_edges_weight: original_edges,
+                                level_weight: level,
+                                .. ContractionConfig::default()
+                              }
+        };
+
+        let shortcuts = contract_node(&remaining, &"c", 20);
+        let ed = edge_difference(&remaining, &"c", &shortcuts);
+        assert_eq!(node_priority(&remaining, &state, &"c", &only(1, 0, 0, 0)), ed);
+        assert_eq!(node_priority(&remaining, &state, &"c", &only(0, 1, 0, 0)), 1);
+        assert_eq!(node_priority(&remaining, &state, &"c", &only(0, 0, 0, 3)), 3);
+        assert_eq!(node_priority(&remaining, &state, &"c", &only(0, 0, 1, 0)),
+                   shortcuts.iter()
+                            .map(|s| state.original_edges(&s.from_id, &"c") +
+                                     state.original_edges(&"c", &s.to_id))
+                            .sum());
+    }
+
     #[test]
     fn contract_all_nodes() {
-        let (nodes, edges, mut graph) = build_full_graph();
+        let (nodes, edges, graph) = build_full_graph();
+        let mut remaining = RemainingGraph::from_graph(&graph);
 
-        let node_order = preorder_nodes(&mut graph);
-        contract_graph(&mut graph, node_order);
+        let mut state = ContractionState::new();
+        let config = ContractionConfig::default();
+        let node_order = preorder_nodes(&remaining, &state, &config);
+        let (orders, hierarchy_edges) = contract_graph(&mut remaining, &mut state, node_order, &config);
 
+        assert!(remaining.node_ids().is_empty());
         for &(id, _, _) in &nodes {
-            assert!(graph.get_edges(&id).iter().all(|edge| !edge.arc_flag));
-            assert!(graph.get_node(&id)
-                         .map(|node|
-                              node.contraction_order.is_some())
-                         .unwrap_or(false))
+            assert!(orders.contains_key(&id));
         }
+        let mut order_values = orders.values().cloned().collect::<Vec<i64>>();
+        order_values.sort();
+        assert_eq!(order_values, (1..10).collect::<Vec<i64>>());
+        assert!(hierarchy_edges.len() >= edges.len() * 2);
+    }
+
+    #[test]
+    fn preprocessing_leaves_graph_untouched() {
+        let (nodes, edges, mut graph) = build_full_graph();
+        graph.get_mut_edge(&"a", &"b").map(|edge| edge.arc_flag = true);
+
+        let hierarchy = preprocess_contraction(&graph);
+
         let edge_count = nodes.iter()
                               .map(|&(id, _, _)| graph.get_edges(&id).len())
                               .fold(0, |sum, l| sum + l);
-        assert!(edge_count >= edges.len() * 2);
+        assert_eq!(edge_count, edges.len() * 2);
+        for node in graph.all_nodes() {
+            for edge in graph.get_edges(&node.id) {
+                assert_eq!(edge.arc_flag, edge.from_id == "a" && edge.to_id == "b");
+            }
+        }
+        assert!(hierarchy.edge_count() >= edge_count);
     }
 
     #[test]
-    fn mark_edges_where_contraction_order_increases() {
-        let (_, _, mut graph) = build_full_graph();
+    fn hierarchy_edges_point_between_levels() {
+        let (_, _, graph) = build_full_graph();
 
-        let node_order = preorder_nodes(&mut graph);
-        contract_graph(&mut graph, node_order);
+        let hierarchy = preprocess_contraction(&graph);
 
-        set_increasing_arc_flags(&mut graph);
-
-        for node in graph.all_nodes() {
-            for edge in graph.get_edges(&node.id) {
-                if graph.get_node(&edge.to_id)
-                        .and_then(|n| n.contraction_order)
-                        .unwrap() >
-                   node.contraction_order.unwrap() {
-                    assert!(edge.arc_flag);
-                } else {
-                    assert!(!edge.arc_flag);
-                }
+        for node_id in hierarchy.nodes_in_order() {
+            let order = hierarchy.contraction_order(&node_id);
+            for edge in hierarchy.upward_edges(&node_id) {
+                assert_eq!(edge.from_id, node_id);
+                assert!(hierarchy.contraction_order(&edge.to_id) > order);
+                assert!(hierarchy.level(&edge.to_id) > hierarchy.level(&node_id));
+            }
+            for edge in hierarchy.downward_edges(&node_id) {
+                assert_eq!(edge.to_id, node_id);
+                assert!(hierarchy.contraction_order(&edge.from_id) > order);
             }
         }
     }
 
     #[test]
-    fn full_preprocessing_returns_walkable_graph() {
-        let (nodes, _, mut graph) = build_full_graph();
+    fn full_preprocessing_returns_walkable_hierarchy() {
+        let (nodes, _, graph) = build_full_graph();
 
-        preprocess_contraction(&mut graph);
+        let hierarchy = preprocess_contraction(&graph);
 
         for (id, _, _) in nodes {
-            let (_, results) = arc_flags_shortest_path(&graph,
-                                                       &id,
-                                                       None);
-            let start_node_contraction = graph.get_node(&id)
-                                              .unwrap()
-                                              .contraction_order
-                                              .unwrap();
+            let results = upward_search(&hierarchy, &id);
+            let start_node_contraction = hierarchy.contraction_order(&id).unwrap();
             let result_contractions: Vec<i64> = results.keys()
-                                                       .map(|id| graph.get_node(id)
-                                                                       .unwrap()
-                                                                       .contraction_order
-                                                                       .unwrap())
+                                                       .map(|id| hierarchy.contraction_order(id).unwrap())
                                                        .collect();
             assert!(result_contractions.iter().all(|&co| co >= start_node_contraction));
         }
@@ -635,10 +1107,10 @@ mod test {
 
     #[test]
     fn find_shortest_path_cost() {
-        let (_, _, mut graph) = build_full_graph();
+        let (_, _, graph) = build_full_graph();
 
-        preprocess_contraction(&mut graph);
-        let result = shortest_path(&graph, &"a", &"i");
+        let hierarchy = preprocess_contraction(&graph);
+        let result = shortest_path(&hierarchy, &"a", &"i");
 
         match result {
             Some((cost, _)) => assert_eq!(cost, 6),
@@ -648,14 +1120,311 @@ mod test {
 
     #[test]
     fn find_shortest_path() {
-        let (_, _, mut graph) = build_full_graph();
+        let (_, _, graph) = build_full_graph();
 
-        preprocess_contraction(&mut graph);
-        let result = shortest_path(&graph, &"a", &"i");
+        let hierarchy = preprocess_contraction(&graph);
+        let result = shortest_path(&hierarchy, &"a", &"i");
 
         match result {
             Some((_, path)) => assert_eq!(path, vec!["a", "d", "e", "h", "i"]),
             None => assert!(false)
         }
     }
+
+    #[test]
+    fn shortest_path_costs_match_dijkstra() {
+        let (nodes, _, graph) = build_full_graph();
+
+        let hierarchy = customize(&build_topology(&graph), &graph);
+
+        for &(from, _, _) in &nodes {
+            for &(to, _, _) in &nodes {
+                let (expected, _) = dijkstra(&graph, &from, Some(&to));
+                let actual = shortest_path(&hierarchy, &from, &to).map(|(cost, _)| cost);
+                assert_eq!(actual, Some(expected));
+            }
+        }
+    }
+
+    #[test]
+    fn preprocessed_costs_match_dijkstra() {
+        let tiny_witness_searches = ContractionConfig { ordering_witness_limit: 1,
+                                                        contraction_witness_limit: 1,
+                                                        .. ContractionConfig::default()
+                                                      };
+        let (nodes, _, graph) = build_full_graph();
+        for config in vec![ContractionConfig::default(), tiny_witness_searches] {
+            let hierarchy = preprocess_contraction_with_config(&graph, &config);
+
+            for &(from, _, _) in &nodes {
+                for &(to, _, _) in &nodes {
+                    let (expected, _) = dijkstra(&graph, &from, Some(&to));
+                    let actual = shortest_path(&hierarchy, &from, &to).map(|(cost, _)| cost);
+                    assert_eq!(actual, Some(expected));
+                }
+            }
+        }
+    }
+
+    fn hierarchy_edge(from_id: &'static str,
+                      to_id: &'static str,
+                      weight: i64,
+                      shortcut: Option<&'static str>) -> HierarchyEdge<&'static str> {
+        HierarchyEdge { id: from_id,
+                        from_id: from_id,
+                        to_id: to_id,
+                        weight: weight,
+                        shortcut: shortcut
+                      }
+    }
+
+    // a - b - c - d - e contracted in the order b, d, c, a, e
+    fn build_nested_hierarchy() -> ContractionHierarchy<&'static str> {
+        let orders: HashMap<&'static str, i64> = vec![("b", 1), ("d", 2), ("c", 3), ("a", 4), ("e", 5)]
+                                                     .into_iter()
+                                                     .collect();
+        let mut edges = vec![];
+        for &(n1, n2, w, shortcut) in &[("a", "b", 1, None),
+                                        ("b", "c", 1, None),
+                                        ("c", "d", 1, None),
+                                        ("d", "e", 1, None),
+                                        ("a", "c", 2, Some("b")),
+                                        ("c", "e", 2, Some("d")),
+                                        ("a", "e", 4, Some("c"))] {
+            edges.push(hierarchy_edge(n1, n2, w, shortcut));
+            edges.push(hierarchy_edge(n2, n1, w, shortcut));
+        }
+        ContractionHierarchy::new(orders, edges)
+    }
+
+    fn dijkstra_path<T>(graph: &Graph<T>, source: &T, destination: &T) -> (i64, Vec<T>)
+       where T: GraphKey {
+        let (cost, results) = dijkstra(graph, source, Some(destination));
+        let mut path = vec![destination.clone()];
+        while let Some(predecessor) = results.get(path.last().unwrap()).and_then(|r| r.predecessor.clone()) {
+            path.push(predecessor);
+        }
+        path.reverse();
+        (cost, path)
+    }
+
+    fn path_cost<T>(graph: &Graph<T>, path: &[T]) -> Option<i64>
+       where T: GraphKey {
+        path.windows(2)
+            .map(|pair| graph.get_edges(&pair[0])
+                             .iter()
+                             .filter(|edge| edge.to_id == pair[1])
+                             .map(|edge| edge.weight)
+                             .min())
+            .fold(Some(0), |total, weight| total.and_then(|t| weight.map(|w| t + w)))
+    }
+
+    #[test]
+    fn unpack_both_halves_of_nested_shortcuts() {
+        let hierarchy = build_nested_hierarchy();
+
+        assert_eq!(unpack_edge(&hierarchy, &"a", &"e"), vec!["a", "b", "c", "d", "e"]);
+        assert_eq!(unpack_edge(&hierarchy, &"e", &"a"), vec!["e", "d", "c", "b", "a"]);
+        assert_eq!(unpack_edge(&hierarchy, &"c", &"e"), vec!["c", "d", "e"]);
+        assert_eq!(unpack_path(&hierarchy, &["b", "a", "e"]), vec!["b", "a", "b", "c", "d", "e"]);
+        assert_eq!(unpack_path(&hierarchy, &["a"]), vec!["a"]);
+    }
+
+    #[test]
+    fn nested_shortcut_paths_match_dijkstra() {
+        let mut graph = Graph::new();
+        for &(id, x) in &[("a", 0.0), ("b", 1.0), ("c", 2.0), ("d", 3.0), ("e", 4.0)] {
+            graph.add_node(id, x, 0.0);
+        }
+        for &(n1, n2) in &[("a", "b"), ("b", "c"), ("c", "d"), ("d", "e")] {
+            graph.add_edge(n1, n1, n2, 1);
+            graph.add_edge(n2, n2, n1, 1);
+        }
+        let hierarchy = build_nested_hierarchy();
+
+        for &from in &["a", "b", "c", "d", "e"] {
+            for &to in &["a", "b", "c", "d", "e"] {
+                assert_eq!(shortest_path(&hierarchy, &from, &to), Some(dijkstra_path(&graph, &from, &to)));
+            }
+        }
+    }
+
+    #[test]
+    fn unpacked_paths_use_original_edges() {
+        let (nodes, _, graph) = build_full_graph();
+        let tiny_witness_searches = ContractionConfig { ordering_witness_limit: 1,
+                                                        contraction_witness_limit: 1,
+                                                        .. ContractionConfig::default()
+                                                      };
+        let hierarchies = vec![preprocess_contraction(&graph),
+                               preprocess_contraction_with_config(&graph, &tiny_witness_searches),
+                               customize(&build_topology(&graph), &graph)];
+
+        for hierarchy in &hierarchies {
+            for &(from, _, _) in &nodes {
+                for &(to, _, _) in &nodes {
+                    let (expected, _) = dijkstra_path(&graph, &from, &to);
+                    let (cost, path) = shortest_path(hierarchy, &from, &to).unwrap();
+                    assert_eq!(path.first(), Some(&from));
+                    assert_eq!(path.last(), Some(&to));
+                    assert_eq!(path_cost(&graph, &path), Some(expected));
+                    assert_eq!(cost, expected);
+                }
+            }
+        }
+    }
+
+    // like the full graph, but some streets are one-way and j can be entered but not left
+    fn build_one_way_graph() -> (Vec<&'static str>, Graph<&'static str>) {
+        let (nodes, edges, _) = build_full_graph();
+        let one_way = vec![("a", "b"), ("d", "e"), ("e", "h"), ("f", "i"), ("c", "f")];
+        let mut graph = Graph::new();
+        for &(id, x, y) in &nodes {
+            graph.add_node(id, x, y);
+        }
+        graph.add_node("j", 3.0, 0.0);
+        for &(n1, n2, w) in &edges {
+            graph.add_edge(n1, n1, n2, w);
+            if !one_way.contains(&(n1, n2)) {
+                graph.add_edge(n2, n2, n1, w);
+            }
+        }
+        graph.add_edge("i", "i", "j", 1);
+
+        let mut node_ids = nodes.iter().map(|&(id, _, _)| id).collect::<Vec<_>>();
+        node_ids.push("j");
+        (node_ids, graph)
+    }
+
+    #[test]
+    fn one_way_paths_match_dijkstra() {
+        let (nodes, graph) = build_one_way_graph();
+        let tiny_witness_searches = ContractionConfig { ordering_witness_limit: 1,
+                                                        contraction_witness_limit: 1,
+                                                        .. ContractionConfig::default()
+                                                      };
+        let hierarchies = vec![preprocess_contraction(&graph),
+                               preprocess_contraction_with_config(&graph, &tiny_witness_searches),
+                               customize(&build_topology(&graph), &graph)];
+
+        for hierarchy in &hierarchies {
+            for &from in &nodes {
+                for &to in &nodes {
+                    let (_, results) = dijkstra(&graph, &from, Some(&to));
+                    match shortest_path(hierarchy, &from, &to) {
+                        Some((cost, path)) => {
+                            assert_eq!(results.get(&to).map(|r| r.cost), Some(cost));
+                            assert_eq!(path_cost(&graph, &path), Some(cost));
+                        }
+                        None => assert!(results.get(&to).is_none())
+                    }
+                }
+            }
+        }
+        assert_eq!(shortest_path(&hierarchies[0], &"j", &"i"), None);
+        assert_eq!(shortest_path(&hierarchies[0], &"b", &"a").map(|(cost, _)| cost), Some(10));
+    }
+
+    #[test]
+    fn independent_nodes_are_not_adjacent() {
+        let (nodes, _, graph) = build_full_graph();
+        let remaining = RemainingGraph::from_graph(&graph);
+        let state = ContractionState::new();
+        let config = ContractionConfig::default();
+        let priorities: HashMap<&'static str, i64> = nodes.iter()
+                                                          .map(|&(id, _, _)| (id, node_priority(&remaining, &state, &id, &config)))
+                                                          .collect();
+
+        let independent = independent_nodes(&remaining, &priorities);
+
+        assert!(!independent.is_empty());
+        let lowest = priorities.values().min().unwrap();
+        assert_eq!(priorities.get(&independent[0]), Some(lowest));
+        for node_id in &independent {
+            for neighbor in remaining.neighbors(node_id) {
+                assert!(!independent.contains(&neighbor));
+                assert!(priorities.get(node_id) <= priorities.get(&neighbor));
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_contraction_matches_dijkstra() {
+        let (full_nodes, _, full_graph) = build_full_graph();
+        let full_nodes = full_nodes.iter().map(|&(id, _, _)| id).collect::<Vec<_>>();
+        let (one_way_nodes, one_way_graph) = build_one_way_graph();
+
+        for &(ref nodes, ref graph) in &[(full_nodes, full_graph), (one_way_nodes, one_way_graph)] {
+            for &threads in &[1, 4] {
+                let hierarchy = preprocess_contraction_in_parallel(graph, &ContractionConfig::default(), threads);
+
+                let mut orders = nodes.iter()
+                                      .map(|id| hierarchy.contraction_order(id).unwrap())
+                                      .collect::<Vec<i64>>();
+                orders.sort();
+                assert_eq!(orders, (1..nodes.len() as i64 + 1).collect::<Vec<i64>>());
+
+                for from in nodes {
+                    for to in nodes {
+                        let (_, results) = dijkstra(graph, from, Some(to));
+                        match shortest_path(&hierarchy, from, to) {
+                            Some((cost, path)) => {
+                                assert_eq!(results.get(to).map(|r| r.cost), Some(cost));
+                                assert_eq!(path_cost(graph, &path), Some(cost));
+                            }
+                            None => assert!(results.get(to).is_none())
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_map_keeps_order_for_any_thread_count() {
+        let items = (0..10).collect::<Vec<i64>>();
+        let doubled = (0..10).map(|i| i * 2).collect::<Vec<i64>>();
+
+        for &threads in &[0, 1, 3, 20] {
+            assert_eq!(parallel_map(&items, threads, |i| i * 2), doubled);
+            assert_eq!(parallel_map(&[] as &[i64], threads, |i| i * 2), Vec::<i64>::new());
+        }
+    }
+
+    #[test]
+    fn no_shortest_path_between_disconnected_nodes() {
+        let (_, _, mut graph) = build_full_graph();
+        graph.add_node("x", 5.0, 5.0);
+
+        let hierarchy = preprocess_contraction(&graph);
+
+        assert_eq!(shortest_path(&hierarchy, &"a", &"x"), None);
+        assert_eq!(shortest_path(&hierarchy, &"x", &"a"), None);
+        assert_eq!(shortest_path(&hierarchy, &"x", &"x"), Some((0, vec!["x"])));
+    }
+
+    #[test]
+    fn stalled_nodes_are_not_expanded() {
+        let orders: HashMap<&'static str, i64> = vec![("low", 1), ("mid", 2), ("high", 3), ("top", 4)]
+                                                     .into_iter()
+                                                     .collect();
+        // low reaches mid directly at cost 5 and via high at cost 2
+        let mut edges = vec![];
+        for &(n1, n2, w) in &[("low", "mid", 5), ("low", "high", 1), ("high", "mid", 1), ("mid", "top", 1)] {
+            edges.push(hierarchy_edge(n1, n2, w, None));
+            edges.push(hierarchy_edge(n2, n1, w, None));
+        }
+        let hierarchy = ContractionHierarchy::new(orders, edges);
+
+        let mut search = UpwardSearch::new(&"low", SearchDirection::Forward);
+        let other = UpwardSearch::new(&"top", SearchDirection::Backward);
+        while search.min_cost().is_some() {
+            search.settle_next(&hierarchy, &other);
+        }
+
+        // mid is settled with cost 5 before high settles it more cheaply from above,
+        // but is stalled and never relaxes its edge to top
+        assert_eq!(search.results.get(&"mid").map(|r| r.cost), Some(5));
+        assert_eq!(search.results.get(&"top"), None);
+    }
 }
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use weighted_graph::GraphKey;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct HierarchyEdge<T: GraphKey> {
+    pub id: T,
+    pub from_id: T,
+    pub to_id: T,
+    pub weight: i64,
+    // the node a shortcut skips over
+    pub shortcut: Option<T>
+}
+
+// kept apart from the graph, so contracting it leaves its edges and arc flags untouched
+#[derive(Debug)]
+pub struct ContractionHierarchy<T: GraphKey> {
+    orders: HashMap<T, i64>,
+    levels: HashMap<T, i64>,
+    // edges from a node to higher nodes
+    upward: HashMap<T, Vec<HierarchyEdge<T>>>,
+    // edges from higher nodes into a node
+    downward: HashMap<T, Vec<HierarchyEdge<T>>>
+}
+
+impl<T: GraphKey> ContractionHierarchy<T> {
+    pub fn new(orders: HashMap<T, i64>, edges: Vec<HierarchyEdge<T>>) -> Self {
+        let mut upward = HashMap::new();
+        let mut downward = HashMap::new();
+        for edge in edges {
+            match (orders.get(&edge.from_id), orders.get(&edge.to_id)) {
+                (Some(from), Some(to)) if from < to => {
+                    upward.entry(edge.from_id.clone()).or_insert(Vec::new()).push(edge);
+                }
+                (Some(from), Some(to)) if from > to => {
+                    downward.entry(edge.to_id.clone()).or_insert(Vec::new()).push(edge);
+                }
+                _ => {}
+            }
+        }
+
+        let mut hierarchy = ContractionHierarchy { orders: orders,
+                                                   levels: HashMap::new(),
+                                                   upward: upward,
+                                                   downward: downward
+                                                 };
+        hierarchy.assign_levels();
+        hierarchy
+    }
+
+    // a node's level is one above the highest level among its lower neighbors
+    fn assign_levels(&mut self) {
+        let mut levels = HashMap::new();
+        for node_id in self.nodes_in_order() {
+            let level = *levels.entry(node_id.clone()).or_insert(0);
+            let higher = self.upward_edges(&node_id)
+                             .iter()
+                             .map(|edge| &edge.to_id)
+                             .chain(self.downward_edges(&node_id).iter().map(|edge| &edge.from_id));
+            for higher_id in higher {
+                let higher_level = levels.entry(higher_id.clone()).or_insert(0);
+                if *higher_level <= level {
+                    *higher_level = level + 1;
+                }
+            }
+        }
+        self.levels = levels;
+    }
+
+    pub fn contraction_order(&self, node_id: &T) -> Option<i64> {
+        self.orders.get(node_id).cloned()
+    }
+
+    pub fn level(&self, node_id: &T) -> Option<i64> {
+        self.levels.get(node_id).cloned()
+    }
+
+    pub fn nodes_in_order(&self) -> Vec<T> {
+        let mut nodes: Vec<(i64, T)> = self.orders
+                                           .iter()
+                                           .map(|(id, &order)| (order, id.clone()))
+                                           .collect();
+        nodes.sort_by(|a, b| a.0.cmp(&b.0));
+        nodes.into_iter().map(|(_, id)| id).collect()
+    }
+
+    pub fn upward_edges(&self, node_id: &T) -> &[HierarchyEdge<T>] {
+        self.upward.get(node_id).map(|edges| &edges[..]).unwrap_or(&[])
+    }
+
+    pub fn downward_edges(&self, node_id: &T) -> &[HierarchyEdge<T>] {
+        self.downward.get(node_id).map(|edges| &edges[..]).unwrap_or(&[])
+    }
+
+    pub fn edge(&self, from_id: &T, to_id: &T) -> Option<&HierarchyEdge<T>> {
+        self.upward_edges(from_id)
+            .iter()
+            .chain(self.downward_edges(to_id).iter())
+            .find(|edge| edge.from_id == *from_id && edge.to_id == *to_id)
+    }
+
+    // the hierarchy of the graph with every edge turned around, for searches towards a node
+    pub fn reversed(&self) -> Self {
+        let edges = self.upward
+                        .values()
+                        .chain(self.downward.values())
+                        .flat_map(|edges| edges.iter())
+                        .map(|edge| HierarchyEdge { id: edge.id.clone(),
+                                                    from_id: edge.to_id.clone(),
+                                                    to_id: edge.from_id.clone(),
+                                                    weight: edge.weight,
+                                                    shortcut: edge.shortcut.clone()
+                                                  })
+                        .collect();
+        ContractionHierarchy::new(self.orders.clone(), edges)
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.upward.values().chain(self.downward.values()).map(|edges| edges.len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use super::{ ContractionHierarchy, HierarchyEdge };
+
+    fn edge(from_id: &'static str,
+            to_id: &'static str,
+            weight: i64,
+            shortcut: Option<&'static str>) -> HierarchyEdge<&'static str> {
+        HierarchyEdge { id: from_id,
+                        from_id: from_id,
+                        to_id: to_id,
+                        weight: weight,
+                        shortcut: shortcut
+                      }
+    }
+
+    fn build_hierarchy() -> ContractionHierarchy<&'static str> {
+        let orders: HashMap<&'static str, i64> = vec![("a", 1), ("b", 2), ("c", 3), ("d", 4)]
+                                                     .into_iter()
+                                                     .collect();
+        let edges = vec![edge("a", "b", 1, None),
+                         edge("b", "a", 1, None),
+                         edge("b", "c", 2, None),
+                         edge("c", "b", 2, None),
+                         edge("a", "d", 5, None),
+                         edge("d", "a", 5, None),
+                         edge("b", "d", 4, Some("a"))];
+        ContractionHierarchy::new(orders, edges)
+    }
+
+    #[test]
+    fn split_edges_by_contraction_order() {
+        let hierarchy = build_hierarchy();
+
+        assert_eq!(hierarchy.edge_count(), 7);
+        assert_eq!(hierarchy.upward_edges(&"a").len(), 2);
+        assert!(hierarchy.upward_edges(&"d").is_empty());
+        assert_eq!(hierarchy.downward_edges(&"a").iter().map(|e| e.from_id).collect::<Vec<_>>(),
+                   vec!["b", "d"]);
+        assert_eq!(hierarchy.edge(&"b", &"d").and_then(|e| e.shortcut), Some("a"));
+        assert_eq!(hierarchy.edge(&"d", &"a").map(|e| e.weight), Some(5));
+        assert_eq!(hierarchy.edge(&"d", &"b"), None);
+    }
+
+    #[test]
+    fn levels_rise_above_lower_neighbors() {
+        let hierarchy = build_hierarchy();
+
+        assert_eq!(hierarchy.nodes_in_order(), vec!["a", "b", "c", "d"]);
+        assert_eq!(hierarchy.level(&"a"), Some(0));
+        assert_eq!(hierarchy.level(&"b"), Some(1));
+        assert_eq!(hierarchy.level(&"c"), Some(2));
+        assert_eq!(hierarchy.level(&"d"), Some(2));
+        assert_eq!(hierarchy.level(&"x"), None);
+    }
+
+    #[test]
+    fn reverse_hierarchy_edges() {
+        let hierarchy = build_hierarchy().reversed();
+
+        assert_eq!(hierarchy.edge_count(), 7);
+        assert_eq!(hierarchy.edge(&"d", &"b").and_then(|e| e.shortcut), Some("a"));
+        assert_eq!(hierarchy.edge(&"b", &"d"), None);
+        assert_eq!(hierarchy.level(&"d"), Some(2));
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::{ BinaryHeap, HashMap, HashSet };
+use std::cmp::Ordering;
+
+use weighted_graph::{ GraphKey, Graph };
+use contraction_hierarchy::{ ContractionHierarchy, HierarchyEdge };
+
+// the order and shortcut topology only depend on the graph's structure,
+// so they are computed once and reused for every metric
+#[derive(Debug)]
+pub struct ContractionTopology<T: GraphKey> {
+    order: HashMap<T, i64>,
+    nodes: Vec<T>,
+    upward: HashMap<T, Vec<T>>
+}
+
+impl<T: GraphKey> ContractionTopology<T> {
+    pub fn contraction_order(&self, node_id: &T) -> Option<i64> {
+        self.order.get(node_id).cloned()
+    }
+
+    pub fn upward_neighbors(&self, node_id: &T) -> &[T] {
+        self.upward.get(node_id).map(|ns| &ns[..]).unwrap_or(&[])
+    }
+
+    pub fn arc_count(&self) -> usize {
+        self.upward.values().map(|ns| ns.len()).sum()
+    }
+}
+
+pub fn build_topology<T>(graph: &Graph<T>) -> ContractionTopology<T>
+       where T: GraphKey {
+    let mut neighbors = undirected_neighbors(graph);
+    let mut order = HashMap::new();
+    let mut nodes = vec![];
+    let mut upward = HashMap::new();
+
+    let mut queue: BinaryHeap<Degree<T>> = neighbors.iter()
+                                                    .map(|(id, ns)| Degree { node_id: id.clone(),
+                                                                             degree: ns.len()
+                                                                           })
+                                                    .collect();
+
+    while let Some(next_node) = queue.pop() {
+        if order.contains_key(&next_node.node_id) {
+            continue;
+        }
+        let remaining = neighbors.get(&next_node.node_id).cloned().unwrap_or(HashSet::new());
+        if remaining.len() != next_node.degree {
+            queue.push(Degree { node_id: next_node.node_id, degree: remaining.len() });
+            continue;
+        }
+
+        // eliminating a node turns its remaining neighbors into a clique
+        for neighbor in &remaining {
+            if let Some(ns) = neighbors.get_mut(neighbor) {
+                ns.remove(&next_node.node_id);
+                for other in &remaining {
+                    if other != neighbor {
+                        ns.insert(other.clone());
+                    }
+                }
+            }
+        }
+        for neighbor in &remaining {
+            let degree = neighbors.get(neighbor).map(|ns| ns.len()).unwrap_or(0);
+            queue.push(Degree { node_id: neighbor.clone(), degree: degree });
+        }
+
+        nodes.push(next_node.node_id.clone());
+        order.insert(next_node.node_id.clone(), nodes.len() as i64);
+        upward.insert(next_node.node_id, remaining.into_iter().collect());
+    }
+
+    ContractionTopology { order: order,
+                          nodes: nodes,
+                          upward: upward
+                        }
+}
+
+fn undirected_neighbors<T>(graph: &Graph<T>) -> HashMap<T, HashSet<T>>
+   where T: GraphKey {
+    let mut neighbors: HashMap<T, HashSet<T>> = graph.all_nodes()
+                                                     .iter()
+                                                     .map(|node| (node.id.clone(), HashSet::new()))
+                                                     .collect();
+    for node in graph.all_nodes() {
+        for edge in graph.get_edges(&node.id).iter().filter(|edge| edge.to_id != node.id) {
+            neighbors.entry(edge.from_id.clone()).or_insert(HashSet::new()).insert(edge.to_id.clone());
+            neighbors.entry(edge.to_id.clone()).or_insert(HashSet::new()).insert(edge.from_id.clone());
+        }
+    }
+    neighbors
+}
+
+#[derive(Clone, Debug)]
+struct ArcWeight<T: GraphKey> {
+    id: T,
+    weight: i64,
+    shortcut: Option<T>
+}
+
+// applies the weights currently on the graph's edges to the topology and
+// returns a hierarchy that `contraction::shortest_path` can query
+pub fn customize<T>(topology: &ContractionTopology<T>, graph: &Graph<T>) -> ContractionHierarchy<T>
+       where T: GraphKey {
+    let mut arcs = initial_arcs(graph);
+
+    for node_id in &topology.nodes {
+        let upper = topology.upward_neighbors(node_id);
+        for from_id in upper {
+            for to_id in upper.iter().filter(|to_id| *to_id != from_id) {
+                let weight_across = match (arcs.get(&(from_id.clone(), node_id.clone())),
+                                           arcs.get(&(node_id.clone(), to_id.clone()))) {
+                    (Some(first), Some(second)) => first.weight + second.weight,
+                    _ => continue
+                };
+                let current = arcs.get(&(from_id.clone(), to_id.clone()))
+                                  .map_or(i64::max_value(), |arc| arc.weight);
+                if weight_across < current {
+                    arcs.insert((from_id.clone(), to_id.clone()),
+                                ArcWeight { id: from_id.clone(),
+                                            weight: weight_across,
+                                            shortcut: Some(node_id.clone())
+                                          });
+                }
+            }
+        }
+    }
+
+    contracted_hierarchy(topology, arcs)
+}
+
+fn initial_arcs<T>(graph: &Graph<T>) -> HashMap<(T, T), ArcWeight<T>>
+   where T: GraphKey {
+    let mut arcs: HashMap<(T, T), ArcWeight<T>> = HashMap::new();
+    for node in graph.all_nodes() {
+        for edge in graph.get_edges(&node.id).iter().filter(|edge| edge.to_id != node.id) {
+            let key = (edge.from_id.clone(), edge.to_id.clone());
+            if arcs.get(&key).map_or(true, |arc| edge.weight < arc.weight) {
+                arcs.insert(key, ArcWeight { id: edge.id.clone(),
+                                             weight: edge.weight,
+                                             shortcut: None
+                                           });
+            }
+        }
+    }
+    arcs
+}
+
+fn contracted_hierarchy<T>(topology: &ContractionTopology<T>,
+                           arcs: HashMap<(T, T), ArcWeight<T>>) -> ContractionHierarchy<T>
+   where T: GraphKey {
+    let edges = arcs.into_iter()
+                    .map(|((from_id, to_id), arc)|
+                        HierarchyEdge { id: arc.id,
+                                        from_id: from_id,
+                                        to_id: to_id,
+                                        weight: arc.weight,
+                                        shortcut: arc.shortcut
+                                      }
+                    )
+                    .collect();
+    ContractionHierarchy::new(topology.order.clone(), edges)
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+struct Degree<T: GraphKey> {
+    node_id: T,
+    degree: usize
+}
+
+impl<T> Ord for Degree<T>
+        where T: GraphKey {
+    // flip order so min-heap instead of max-heap
+    fn cmp(&self, other: &Degree<T>) -> Ordering {
+        other.degree.cmp(&self.degree)
+    }
+}
+
+impl<T> PartialOrd for Degree<T>
+        where T: GraphKey {
+    fn partial_cmp(&self, other: &Degree<T>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use weighted_graph::Graph;
+    use contraction_hierarchy::ContractionHierarchy;
+    use dijkstra::shortest_path as dijkstra;
+    use contraction::shortest_path;
+    use super::{ build_topology, customize };
+
+    fn build_full_graph() -> (Vec<(&'static str, f64, f64)>, // nodes
+                              Vec<(&'static str, &'static str, i64)>, // edges
+                              Graph<&'static str>) {
+        let mut graph = Graph::new();
+        let nodes = vec![("a", 0.0, 3.0),
+                         ("b", 0.0, 1.0),
+                         ("c", 0.0, 0.0),
+                         ("d", 1.0, 3.0),
+                         ("e", 1.0, 2.0),
+                         ("f", 1.0, 0.0),
+                         ("g", 2.0, 3.0),
+                         ("h", 2.0, 1.0),
+                         ("i", 2.0, 0.0)];
+        for &(id, x, y) in &nodes {
+            graph.add_node(id, x, y);
+        }
+
+        let edges = vec![("a", "b", 3),
+                         ("a", "d", 2),
+                         ("b", "c", 1),
+                         ("b", "e", 1),
+                         ("c", "f", 2),
+                         ("d", "e", 1),
+                         ("d", "g", 2),
+                         ("e", "f", 3),
+                         ("e", "h", 1),
+                         ("f", "i", 2),
+                         ("g", "h", 4),
+                         ("h", "i", 2),
+                        ];
+        for &(n1, n2, w) in &edges {
+            graph.add_edge(n1, n1, n2, w);
+            graph.add_edge(n2, n2, n1, w);
+        }
+
+        (nodes, edges, graph)
+    }
+
+    fn assert_matches_dijkstra(graph: &Graph<&'static str>,
+                               hierarchy: &ContractionHierarchy<&'static str>,
+                               nodes: &Vec<(&'static str, f64, f64)>) {
+        for &(from, _, _) in nodes {
+            for &(to, _, _) in nodes {
+                let (expected, _) = dijkstra(graph, &from, Some(&to));
+                let (actual, _) = shortest_path(hierarchy, &from, &to).unwrap();
+                assert_eq!(actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn order_every_node() {
+        let (nodes, _, graph) = build_full_graph();
+
+        let topology = build_topology(&graph);
+
+        let mut orders = nodes.iter()
+                              .map(|&(id, _, _)| topology.contraction_order(&id).unwrap())
+                              .collect::<Vec<i64>>();
+        orders.sort();
+        assert_eq!(orders, (1..10).collect::<Vec<i64>>());
+    }
+
+    #[test]
+    fn upward_neighbors_form_cliques() {
+        let (nodes, edges, graph) = build_full_graph();
+
+        let topology = build_topology(&graph);
+
+        assert!(topology.arc_count() >= edges.len());
+        for &(id, _, _) in &nodes {
+            let order = topology.contraction_order(&id);
+            let upper = topology.upward_neighbors(&id);
+            for neighbor in upper {
+                assert!(topology.contraction_order(neighbor) > order);
+                for other in upper.iter().filter(|other| *other != neighbor) {
+                    let (low, high) = if topology.contraction_order(neighbor) <
+                                         topology.contraction_order(other) {
+                        (neighbor, other)
+                    } else {
+                        (other, neighbor)
+                    };
+                    assert!(topology.upward_neighbors(low).contains(high));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn customized_hierarchy_follows_topology() {
+        let (nodes, _, graph) = build_full_graph();
+        let topology = build_topology(&graph);
+
+        let hierarchy = customize(&topology, &graph);
+
+        for &(id, _, _) in &nodes {
+            assert_eq!(hierarchy.contraction_order(&id), topology.contraction_order(&id));
+            for edge in hierarchy.upward_edges(&id) {
+                assert!(topology.upward_neighbors(&id).contains(&edge.to_id));
+            }
+            for edge in hierarchy.downward_edges(&id) {
+                assert!(topology.upward_neighbors(&id).contains(&edge.from_id));
+            }
+        }
+    }
+
+    #[test]
+    fn customized_queries_match_dijkstra() {
+        let (nodes, _, graph) = build_full_graph();
+
+        let hierarchy = customize(&build_topology(&graph), &graph);
+
+        assert_matches_dijkstra(&graph, &hierarchy, &nodes);
+    }
+
+    #[test]
+    fn recustomize_after_weight_change() {
+        let (nodes, _, mut graph) = build_full_graph();
+        let topology = build_topology(&graph);
+
+        for &(from, to) in &[("e", "h"), ("h", "e"), ("a", "d"), ("d", "a")] {
+            graph.get_mut_edge(&from, &to).map(|edge| edge.weight = 10);
+        }
+        let hierarchy = customize(&topology, &graph);
+
+        assert_eq!(shortest_path(&hierarchy, &"a", &"i").map(|(cost, _)| cost), Some(8));
+        assert_matches_dijkstra(&graph, &hierarchy, &nodes);
+    }
+}
@@ -130,8 +130,7 @@ mod test {
             Some(node) => {
                 node == &Node { id: "292403538".to_string(),
                                 x: 12.2482632,
-                                y: 54.0901746,
-                                contraction_order: None
+                                y: 54.0901746
                               }
             }
             None => false
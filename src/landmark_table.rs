@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+use std::fmt::{ Debug, Display };
+use std::fs::File;
+use std::io::{ self, BufRead, BufReader, Write };
+use std::str::FromStr;
+
+use weighted_graph::{ GraphKey, Graph, Node };
+use pathfinder::HeuristicFn;
+use a_star_heuristics::LandmarkDistances;
+use load_error::{ LoadError, next_line, parse };
+
+// a distance divided by the table's scale and rounded down, with the largest value kept
+// for nodes that are unreachable or too far to fit
+pub trait CompactDistance: Copy + PartialEq + Debug + Display + FromStr {
+    fn compress(distance: i64, scale: i64) -> Self;
+    fn quotient(self) -> Option<i64>;
+    fn unreachable() -> Self;
+}
+
+impl CompactDistance for u32 {
+    fn compress(distance: i64, scale: i64) -> Self {
+        let quotient = distance / scale;
+        if quotient >= 0 && quotient < u32::max_value() as i64 { quotient as u32 } else { u32::max_value() }
+    }
+
+    fn quotient(self) -> Option<i64> {
+        if self == u32::max_value() { None } else { Some(self as i64) }
+    }
+
+    fn unreachable() -> Self {
+        u32::max_value()
+    }
+}
+
+impl CompactDistance for u16 {
+    fn compress(distance: i64, scale: i64) -> Self {
+        let quotient = distance / scale;
+        if quotient >= 0 && quotient < u16::max_value() as i64 { quotient as u16 } else { u16::max_value() }
+    }
+
+    fn quotient(self) -> Option<i64> {
+        if self == u16::max_value() { None } else { Some(self as i64) }
+    }
+
+    fn unreachable() -> Self {
+        u16::max_value()
+    }
+}
+
+// landmark distances for every node in one dense vector: per node, the distance
+// from each landmark followed by the distance to each landmark
+#[derive(PartialEq, Debug)]
+pub struct LandmarkTable<T: GraphKey, V: CompactDistance> {
+    fingerprint: u64,
+    scale: i64,
+    landmarks: Vec<T>,
+    index: HashMap<T, usize>,
+    values: Vec<V>
+}
+
+impl<T: GraphKey, V: CompactDistance> LandmarkTable<T, V> {
+    // rows follow the node ids in order, so the same graph always gives the same file
+    pub fn new(graph: &Graph<T>, landmarks: Vec<T>, distances: &[LandmarkDistances<T>], scale: i64) -> Self
+           where T: Ord + Display {
+        let scale = scale.max(1);
+        let mut index = HashMap::new();
+        let mut values = Vec::with_capacity(graph.all_nodes().len() * distances.len() * 2);
+        for (i, id) in graph.sorted_node_ids().into_iter().enumerate() {
+            let compress = |distance: Option<&i64>| distance.map_or(V::unreachable(), |&d| V::compress(d, scale));
+            values.extend(distances.iter().map(|d| compress(d.from.get(&id))));
+            values.extend(distances.iter().map(|d| compress(d.to.get(&id))));
+            index.insert(id, i);
+        }
+        LandmarkTable { fingerprint: graph_fingerprint(graph),
+                        scale: scale,
+                        landmarks: landmarks,
+                        index: index,
+                        values: values
+                      }
+    }
+
+    pub fn landmarks(&self) -> &[T] {
+        &self.landmarks
+    }
+
+    // true if the table was computed on a graph with the same nodes and edges
+    pub fn matches(&self, graph: &Graph<T>) -> bool
+           where T: Ord + Display {
+        self.fingerprint == graph_fingerprint(graph)
+    }
+
+    fn row(&self, node_id: &T) -> Option<&[V]> {
+        let width = self.landmarks.len() * 2;
+        self.index.get(node_id).map(|&i| &self.values[i * width..(i + 1) * width])
+    }
+
+    // each stored quotient q stands for a distance in [q * scale, q * scale + scale - 1],
+    // so a difference of quotients loses up to scale - 1 against the real one
+    pub fn lower_bound(&self, current: &T, target: &T) -> i64 {
+        let count = self.landmarks.len();
+        let (current, target) = match (self.row(current), self.row(target)) {
+            (Some(current), Some(target)) => (current, target),
+            _ => return 0
+        };
+        (0..count).flat_map(|l| vec![(target[l], current[l]), (current[count + l], target[count + l])])
+                  .filter_map(|(larger, smaller)|
+                      larger.quotient().and_then(|larger|
+                          smaller.quotient().map(|smaller| self.scale * (larger - smaller) - (self.scale - 1))
+                      )
+                  )
+                  .max()
+                  .unwrap_or(0)
+                  .max(0)
+    }
+
+    pub fn heuristic<'a>(self) -> HeuristicFn<'a, T>
+           where T: 'a,
+                 V: 'a {
+        Box::new(move |current: Option<&Node<T>>, target: Option<&Node<T>>| {
+            match (current, target) {
+                (Some(c_node), Some(t_node)) => self.lower_bound(&c_node.id, &t_node.id),
+                _ => 0
+            }
+        })
+    }
+
+    // a header line, the landmarks one per line, then a line per node of its
+    // id and its tab separated values
+    pub fn write_to<W>(&self, writer: &mut W) -> io::Result<()>
+           where W: Write,
+                 T: Display {
+        writeln!(writer, "landmark_table {} {} {} {}",
+                 self.fingerprint, self.scale, self.landmarks.len(), self.index.len())?;
+        for landmark in &self.landmarks {
+            writeln!(writer, "{}", landmark)?;
+        }
+        let mut nodes: Vec<(&usize, &T)> = self.index.iter().map(|(id, i)| (i, id)).collect();
+        nodes.sort_by_key(|&(i, _)| *i);
+        for (_, id) in nodes {
+            let mut line = id.to_string();
+            for value in self.row(id).unwrap() {
+                line.push_str(&format!("\t{}", value));
+            }
+            writeln!(writer, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R>(reader: R, graph: &Graph<T>) -> Result<Self, LoadError>
+           where R: BufRead,
+                 T: FromStr + Ord + Display {
+        let mut lines = reader.lines();
+        let header_line = next_line(&mut lines)?;
+        let header: Vec<&str> = header_line.split_whitespace().collect();
+        if header.len() != 5 || header[0] != "landmark_table" {
+            return Err(LoadError::Malformed)
+        }
+        let fingerprint: u64 = parse(header[1])?;
+        let scale: i64 = parse(header[2])?;
+        let landmark_count: usize = parse(header[3])?;
+        let node_count: usize = parse(header[4])?;
+        if fingerprint != graph_fingerprint(graph) {
+            return Err(LoadError::GraphMismatch)
+        }
+
+        let mut landmarks = Vec::with_capacity(landmark_count);
+        for _ in 0..landmark_count {
+            landmarks.push(parse(&next_line(&mut lines)?)?);
+        }
+        // every row holds exactly a distance from and to each landmark
+        let mut index = HashMap::new();
+        let mut values = Vec::with_capacity(node_count * landmark_count * 2);
+        for _ in 0..node_count {
+            let line = next_line(&mut lines)?;
+            let mut fields = line.split('\t');
+            let id = parse(fields.next().unwrap_or(""))?;
+            let row_start = values.len();
+            for value in fields {
+                values.push(parse::<V>(value)?);
+            }
+            if values.len() - row_start != landmark_count * 2 {
+                return Err(LoadError::Malformed)
+            }
+            let next = index.len();
+            index.insert(id, next);
+        }
+        if index.len() != node_count {
+            return Err(LoadError::Malformed)
+        }
+
+        Ok(LandmarkTable { fingerprint: fingerprint,
+                           scale: scale,
+                           landmarks: landmarks,
+                           index: index,
+                           values: values
+                         })
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()>
+           where T: Display {
+        let mut file = File::create(path)?;
+        self.write_to(&mut file)
+    }
+
+    pub fn load(path: &str, graph: &Graph<T>) -> Result<Self, LoadError>
+           where T: FromStr + Ord + Display {
+        let file = File::open(path).map_err(LoadError::Io)?;
+        LandmarkTable::read_from(BufReader::new(file), graph)
+    }
+}
+
+// FNV-1a over the nodes and edges in id order, with ids as they are written to the file
+pub fn graph_fingerprint<T>(graph: &Graph<T>) -> u64
+       where T: GraphKey + Ord + Display {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    {
+        let mut feed = |text: String| {
+            for byte in text.bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        for id in graph.sorted_node_ids() {
+            let node = graph.get_node(&id).unwrap();
+            feed(format!("{}|{}|{}|", id, node.x.to_bits(), node.y.to_bits()));
+            let mut edges: Vec<(&T, i64)> = graph.get_edges(&id)
+                                                 .iter()
+                                                 .map(|edge| (&edge.to_id, edge.weight))
+                                                 .collect();
+            edges.sort();
+            for (to_id, weight) in edges {
+                feed(format!("{}>{}|", to_id, weight));
+            }
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod test {
+    use weighted_graph::Graph;
+    use dijkstra::shortest_path as dijkstra;
+    use a_star_heuristics::build_landmark_distances;
+    use load_error::LoadError;
+    use super::{ LandmarkTable, graph_fingerprint };
+
+    fn build_graph() -> Graph<String> {
+        let mut graph = Graph::new();
+        for &(id, x, y) in &[("1", 1.0, 1.0), ("2", 2.0, 4.0), ("3", 3.0, 2.0), ("4", 4.0, 1.0)] {
+            graph.add_node(id.to_string(), x, y);
+        }
+        let edges = vec![("a", "1", "2", 7),
+                         ("b", "2", "3", 5),
+                         ("c", "3", "4", 9),
+                         ("d", "4", "1", 6),
+                         ("e", "2", "1", 30)];
+        for (edge_id, from, to, cost) in edges {
+            graph.add_edge(edge_id.to_string(), from.to_string(), to.to_string(), cost);
+        }
+        graph
+    }
+
+    fn is_malformed<T>(result: Result<T, LoadError>) -> bool {
+        match result {
+            Err(LoadError::Malformed) => true,
+            _ => false
+        }
+    }
+
+    fn ids() -> Vec<String> {
+        vec!["1", "2", "3", "4"].into_iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn compact_bounds_stay_admissible() {
+        let graph = build_graph();
+        let landmarks = vec!["1".to_string(), "3".to_string()];
+        let distances = build_landmark_distances(&graph, landmarks.clone());
+
+        for scale in vec![1, 2, 4, 10] {
+            let wide: LandmarkTable<String, u32> = LandmarkTable::new(&graph, landmarks.clone(), &distances, scale);
+            let narrow: LandmarkTable<String, u16> = LandmarkTable::new(&graph, landmarks.clone(), &distances, scale);
+            for from in &ids() {
+                for to in &ids() {
+                    let (cost, _) = dijkstra(&graph, from, Some(to));
+                    assert!(wide.lower_bound(from, to) <= cost);
+                    assert_eq!(narrow.lower_bound(from, to), wide.lower_bound(from, to));
+                }
+            }
+        }
+
+        // exact at scale 1: d(1, 3) - d(1, 1)
+        let exact: LandmarkTable<String, u32> = LandmarkTable::new(&graph, landmarks, &distances, 1);
+        assert_eq!(exact.lower_bound(&"1".to_string(), &"3".to_string()), 12);
+    }
+
+    #[test]
+    fn save_and_load_table() {
+        let graph = build_graph();
+        let landmarks = vec!["2".to_string()];
+        let distances = build_landmark_distances(&graph, landmarks.clone());
+        let table: LandmarkTable<String, u16> = LandmarkTable::new(&graph, landmarks, &distances, 3);
+        let mut text = Vec::new();
+
+        table.write_to(&mut text).unwrap();
+
+        assert_eq!(LandmarkTable::read_from(&text[..], &graph).ok(), Some(table));
+        assert!(is_malformed(LandmarkTable::<String, u16>::read_from(&b"landmark_table 1 2"[..], &graph)));
+        match LandmarkTable::<String, u16>::load("data/no_such_table.txt", &graph) {
+            Err(LoadError::Io(_)) => (),
+            other => panic!("expected an io error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn reject_corrupt_rows() {
+        let graph = build_graph();
+        let landmarks = vec!["2".to_string()];
+        let distances = build_landmark_distances(&graph, landmarks.clone());
+        let table: LandmarkTable<String, u32> = LandmarkTable::new(&graph, landmarks, &distances, 1);
+        let mut text = Vec::new();
+        table.write_to(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let load = |text: String| LandmarkTable::<String, u32>::read_from(text.as_bytes(), &graph);
+        // the header and the one landmark come before the rows
+        let lines: Vec<String> = text.lines().map(|line| line.to_string()).collect();
+        let with_rows = |first: String, second: String| {
+            let mut changed = lines.clone();
+            changed[2] = first;
+            changed[3] = second;
+            changed.join("\n")
+        };
+        let (last, rest) = {
+            let mut parts = lines[2].rsplitn(2, '\t');
+            (parts.next().unwrap().to_string(), parts.next().unwrap().to_string())
+        };
+
+        assert!(load(text.clone()).is_ok());
+        // a row one short and the next one long keeps the total but shifts the columns
+        assert!(is_malformed(load(with_rows(rest, format!("{}\t{}", lines[3], last)))));
+        assert!(is_malformed(load(with_rows(format!("{}x", lines[2]), lines[3].clone()))));
+    }
+
+    #[test]
+    fn detect_a_different_graph() {
+        let graph = build_graph();
+        let mut changed = build_graph();
+        changed.get_mut_edge(&"1".to_string(), &"2".to_string()).map(|edge| edge.weight = 8);
+        let landmarks = vec!["2".to_string()];
+        let distances = build_landmark_distances(&graph, landmarks.clone());
+        let table: LandmarkTable<String, u32> = LandmarkTable::new(&graph, landmarks, &distances, 1);
+        let mut text = Vec::new();
+        table.write_to(&mut text).unwrap();
+
+        assert_eq!(graph_fingerprint(&graph), graph_fingerprint(&build_graph()));
+        assert!(table.matches(&graph));
+        assert!(!table.matches(&changed));
+        match LandmarkTable::<String, u32>::read_from(&text[..], &changed) {
+            Err(LoadError::GraphMismatch) => (),
+            other => panic!("expected a graph mismatch, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn rows_and_fingerprint_follow_written_ids() {
+        let graph = build_graph();
+        let mut renamed = Graph::new();
+        for node in graph.all_nodes() {
+            let id = if node.id == "4" { "5".to_string() } else { node.id.clone() };
+            renamed.add_node(id, node.x, node.y);
+        }
+        let landmarks = vec!["1".to_string()];
+        let distances = build_landmark_distances(&graph, landmarks.clone());
+        let table: LandmarkTable<String, u32> = LandmarkTable::new(&graph, landmarks, &distances, 1);
+        let mut text = Vec::new();
+        table.write_to(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+
+        let rows: Vec<&str> = text.lines().skip(2).map(|line| line.split('\t').next().unwrap()).collect();
+        assert_eq!(rows, vec!["1", "2", "3", "4"]);
+        let mut unrenamed = Graph::new();
+        for node in graph.all_nodes() {
+            unrenamed.add_node(node.id.clone(), node.x, node.y);
+        }
+        assert!(graph_fingerprint(&renamed) != graph_fingerprint(&unrenamed));
+    }
+
+    #[test]
+    fn heuristic_from_table() {
+        let graph = build_graph();
+        let landmarks = vec!["1".to_string()];
+        let distances = build_landmark_distances(&graph, landmarks.clone());
+        let table: LandmarkTable<String, u32> = LandmarkTable::new(&graph, landmarks, &distances, 1);
+
+        let heuristic = table.heuristic();
+
+        assert_eq!(heuristic(graph.get_node(&"1".to_string()), graph.get_node(&"3".to_string())), 12);
+        assert_eq!(heuristic(graph.get_node(&"1".to_string()), None), 0);
+    }
+}
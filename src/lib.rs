@@ -22,3 +22,14 @@ pub mod gtfs_dijkstra;
 pub mod pareto_sets;
 pub mod set_dijkstra;
 pub mod transfer_patterns;
+pub mod time_dependent_weights;
+pub mod time_dependent_dijkstra;
+pub mod traffic_profiles;
+pub mod customizable_contraction;
+pub mod contraction_hierarchy;
+pub mod phast;
+pub mod many_to_many;
+pub mod partition;
+pub mod transit_node_table;
+pub mod landmark_table;
+pub mod load_error;
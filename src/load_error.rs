@@ -0,0 +1,25 @@
+use std::io;
+use std::str::FromStr;
+
+// why a table saved as text could not be loaded back
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    // not a complete table, or a value in it does not parse
+    Malformed,
+    // the table was computed on a graph with other nodes or edges
+    GraphMismatch
+}
+
+// a missing line is a truncated table, not the end of one
+pub fn next_line<I>(lines: &mut I) -> Result<String, LoadError>
+       where I: Iterator<Item=io::Result<String>> {
+    match lines.next() {
+        Some(line) => line.map_err(LoadError::Io),
+        None => Err(LoadError::Malformed)
+    }
+}
+
+pub fn parse<F: FromStr>(text: &str) -> Result<F, LoadError> {
+    text.parse().map_err(|_| LoadError::Malformed)
+}
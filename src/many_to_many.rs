@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use weighted_graph::{ GraphKey, Graph };
+use dijkstra::shortest_path as dijkstra;
+use contraction::{ upward_search, backward_upward_search };
+use contraction_hierarchy::ContractionHierarchy;
+
+// cost of the shortest path for each (source, target) pair,
+// pairs without a path are left out
+pub type CostMatrix<T> = HashMap<(T, T), i64>;
+
+pub fn many_to_many<T>(graph: &Graph<T>, sources: &[T], targets: &[T]) -> CostMatrix<T>
+       where T: GraphKey {
+    let mut matrix = HashMap::new();
+    for source in sources {
+        let (_, results) = dijkstra(graph, source, None);
+        for target in targets {
+            if let Some(best) = results.get(target) {
+                matrix.insert((source.clone(), target.clone()), best.cost);
+            }
+        }
+    }
+    matrix
+}
+
+// the backward search from every target leaves (target, cost) entries in the bucket
+// of each node it reaches, forward searches from the sources then only scan buckets
+pub fn ch_many_to_many<T>(hierarchy: &ContractionHierarchy<T>,
+                          sources: &[T],
+                          targets: &[T]) -> CostMatrix<T>
+       where T: GraphKey {
+    let mut buckets: HashMap<T, Vec<(T, i64)>> = HashMap::new();
+    for target in targets {
+        for (node_id, best) in backward_upward_search(hierarchy, target) {
+            buckets.entry(node_id).or_insert(Vec::new()).push((target.clone(), best.cost));
+        }
+    }
+
+    let mut matrix = HashMap::new();
+    for source in sources {
+        for (node_id, best) in upward_search(hierarchy, source) {
+            for &(ref target, cost) in buckets.get(&node_id).map(|b| &b[..]).unwrap_or(&[]) {
+                let key = (source.clone(), target.clone());
+                let total = best.cost + cost;
+                if matrix.get(&key).map_or(true, |&current| total < current) {
+                    matrix.insert(key, total);
+                }
+            }
+        }
+    }
+    matrix
+}
+
+#[cfg(test)]
+mod test {
+    use weighted_graph::Graph;
+    use dijkstra::shortest_path as dijkstra;
+    use contraction::preprocess_contraction;
+    use customizable_contraction::{ build_topology, customize };
+    use super::{ many_to_many, ch_many_to_many };
+
+    fn build_graph(one_way: &[(&'static str, &'static str)]) -> (Vec<&'static str>, Graph<&'static str>) {
+        let mut graph = Graph::new();
+        let nodes = vec![("a", 0.0, 3.0),
+                         ("b", 0.0, 1.0),
+                         ("c", 0.0, 0.0),
+                         ("d", 1.0, 3.0),
+                         ("e", 1.0, 2.0),
+                         ("f", 1.0, 0.0),
+                         ("g", 2.0, 3.0),
+                         ("h", 2.0, 1.0),
+                         ("i", 2.0, 0.0),
+                         ("j", 3.0, 0.0)];
+        for &(id, x, y) in &nodes {
+            graph.add_node(id, x, y);
+        }
+
+        let edges = vec![("a", "b", 3),
+                         ("a", "d", 2),
+                         ("b", "c", 1),
+                         ("b", "e", 1),
+                         ("c", "f", 2),
+                         ("d", "e", 1),
+                         ("d", "g", 2),
+                         ("e", "f", 3),
+                         ("e", "h", 1),
+                         ("f", "i", 2),
+                         ("g", "h", 4),
+                         ("h", "i", 2),
+                        ];
+        for &(n1, n2, w) in &edges {
+            graph.add_edge(n1, n1, n2, w);
+            if !one_way.contains(&(n1, n2)) {
+                graph.add_edge(n2, n2, n1, w);
+            }
+        }
+        // j can be reached but never left
+        graph.add_edge("i", "i", "j", 1);
+
+        (nodes.iter().map(|&(id, _, _)| id).collect(), graph)
+    }
+
+    #[test]
+    fn plain_matrix_matches_dijkstra() {
+        let (nodes, graph) = build_graph(&[]);
+
+        let matrix = many_to_many(&graph, &nodes, &nodes);
+
+        for &source in &nodes {
+            let (_, results) = dijkstra(&graph, &source, None);
+            for &target in &nodes {
+                assert_eq!(matrix.get(&(source, target)), results.get(&target).map(|r| &r.cost));
+            }
+        }
+        assert_eq!(matrix.get(&("j", "a")), None);
+        assert_eq!(matrix.get(&("a", "j")), Some(&7));
+    }
+
+    #[test]
+    fn bucket_matrix_matches_plain_matrix() {
+        for one_way in &[vec![], vec![("a", "b"), ("d", "e"), ("e", "h"), ("f", "i"), ("c", "f")]] {
+            let (nodes, graph) = build_graph(one_way);
+            let expected = many_to_many(&graph, &nodes, &nodes);
+
+            assert_eq!(ch_many_to_many(&preprocess_contraction(&graph), &nodes, &nodes), expected);
+            assert_eq!(ch_many_to_many(&customize(&build_topology(&graph), &graph), &nodes, &nodes),
+                       expected);
+        }
+    }
+
+    #[test]
+    fn sources_and_targets_can_differ() {
+        let (_, graph) = build_graph(&[]);
+        let hierarchy = preprocess_contraction(&graph);
+        let sources = vec!["a", "c"];
+        let targets = vec!["g", "i", "j"];
+
+        let matrix = ch_many_to_many(&hierarchy, &sources, &targets);
+
+        assert_eq!(matrix.len(), 6);
+        assert_eq!(matrix, many_to_many(&graph, &sources, &targets));
+        assert_eq!(ch_many_to_many(&hierarchy, &targets, &sources).len(), 4);
+    }
+}
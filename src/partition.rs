@@ -0,0 +1,343 @@
+use std::collections::{ HashMap, HashSet, VecDeque };
+
+use weighted_graph::{ GraphKey, Graph, Node };
+
+// every node of a graph assigned to one of cell_count cells, numbered from 0
+#[derive(Clone, PartialEq, Debug)]
+pub struct Partition<T: GraphKey> {
+    cells: HashMap<T, usize>,
+    cell_count: usize
+}
+
+impl<T: GraphKey> Partition<T> {
+    pub fn new(cells: HashMap<T, usize>) -> Self {
+        let cell_count = cells.values().max().map_or(0, |&max| max + 1);
+        Partition { cells: cells, cell_count: cell_count }
+    }
+
+    pub fn cell(&self, node_id: &T) -> Option<usize> {
+        self.cells.get(node_id).cloned()
+    }
+
+    pub fn cells(&self) -> &HashMap<T, usize> {
+        &self.cells
+    }
+
+    pub fn cell_count(&self) -> usize {
+        self.cell_count
+    }
+
+    pub fn nodes_in_cell(&self, cell: usize) -> Vec<T> {
+        self.cells
+            .iter()
+            .filter(|&(_, &c)| c == cell)
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    // nodes of the cell with an edge into or out of another cell
+    pub fn boundary_nodes(&self, graph: &Graph<T>, cell: usize) -> Vec<T> {
+        let mut boundary = HashSet::new();
+        for (from_id, to_id) in self.cut_edges(graph) {
+            for node_id in vec![from_id, to_id] {
+                if self.cell(&node_id) == Some(cell) {
+                    boundary.insert(node_id);
+                }
+            }
+        }
+        boundary.into_iter().collect()
+    }
+
+    // edges whose endpoints lie in different cells
+    pub fn cut_edges(&self, graph: &Graph<T>) -> Vec<(T, T)> {
+        graph.all_nodes()
+             .iter()
+             .flat_map(|node| graph.get_edges(&node.id).iter())
+             .filter(|edge| self.cell(&edge.from_id) != self.cell(&edge.to_id))
+             .map(|edge| (edge.from_id.clone(), edge.to_id.clone()))
+             .collect()
+    }
+}
+
+// recursively splits the nodes at the median of their wider coordinate
+pub fn kd_tree_partition<T>(graph: &Graph<T>, cell_count: usize) -> Partition<T>
+       where T: GraphKey {
+    let nodes = graph.all_nodes();
+    let cell_count = cell_count.max(1).min(nodes.len().max(1));
+    let mut cells = HashMap::new();
+    kd_split(nodes, cell_count, 0, &mut cells);
+    Partition::new(without_gaps(cells))
+}
+
+fn kd_split<T>(mut nodes: Vec<&Node<T>>, cell_count: usize, first_cell: usize, cells: &mut HashMap<T, usize>)
+   where T: GraphKey {
+    if cell_count <= 1 || nodes.len() <= 1 {
+        for node in nodes {
+            cells.insert(node.id.clone(), first_cell);
+        }
+        return;
+    }
+
+    let spread = |values: Vec<f64>| {
+        values.iter().cloned().fold(f64::NEG_INFINITY, f64::max) -
+            values.iter().cloned().fold(f64::INFINITY, f64::min)
+    };
+    let by_x = spread(nodes.iter().map(|node| node.x).collect()) >=
+               spread(nodes.iter().map(|node| node.y).collect());
+    nodes.sort_by(|a, b| {
+        let (a, b) = if by_x { (a.x, b.x) } else { (a.y, b.y) };
+        a.partial_cmp(&b).unwrap()
+    });
+
+    let (left_cells, split) = split_sizes(nodes.len(), cell_count);
+    let right = nodes.split_off(split);
+    kd_split(nodes, left_cells, first_cell, cells);
+    kd_split(right, cell_count - left_cells, first_cell + left_cells, cells);
+}
+
+// cells for the first half, and how many nodes go with them; both halves get a node
+fn split_sizes(node_count: usize, cell_count: usize) -> (usize, usize) {
+    let left_cells = cell_count / 2;
+    (left_cells, (node_count * left_cells / cell_count).max(1).min(node_count - 1))
+}
+
+// a cut can leave a side with fewer nodes than cells, so unused cell ids are closed up
+fn without_gaps<T>(cells: HashMap<T, usize>) -> HashMap<T, usize>
+   where T: GraphKey {
+    let mut used: Vec<usize> = cells.values().cloned().collect();
+    used.sort();
+    used.dedup();
+    let renumbered: HashMap<usize, usize> = used.into_iter().enumerate().map(|(new, old)| (old, new)).collect();
+    cells.into_iter().map(|(id, cell)| (id, renumbered[&cell])).collect()
+}
+
+// recursive bisection: the nodes are projected onto a few directions, the first and
+// last quarter along each direction are joined by a minimum cut, and the smallest cut wins
+pub fn inertial_flow_partition<T>(graph: &Graph<T>, cell_count: usize) -> Partition<T>
+       where T: GraphKey {
+    let nodes = graph.all_nodes();
+    let cell_count = cell_count.max(1).min(nodes.len().max(1));
+    let mut cells = HashMap::new();
+    flow_split(graph, nodes, cell_count, 0, &mut cells);
+    Partition::new(without_gaps(cells))
+}
+
+const DIRECTIONS: [(f64, f64); 4] = [(1.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, -1.0)];
+const TERMINAL_FRACTION: f64 = 0.25;
+
+fn flow_split<T>(graph: &Graph<T>,
+                 nodes: Vec<&Node<T>>,
+                 cell_count: usize,
+                 first_cell: usize,
+                 cells: &mut HashMap<T, usize>)
+   where T: GraphKey {
+    if cell_count <= 1 || nodes.len() <= 1 {
+        for node in nodes {
+            cells.insert(node.id.clone(), first_cell);
+        }
+        return;
+    }
+
+    let (left_cells, split) = split_sizes(nodes.len(), cell_count);
+    let best = DIRECTIONS.iter()
+                         .map(|&direction| min_cut(graph, &nodes, direction, split))
+                         .min_by_key(|&(cut, _)| cut)
+                         .map(|(_, source_side)| source_side)
+                         .unwrap();
+
+    let (left, right): (Vec<&Node<T>>, Vec<&Node<T>>) = nodes.into_iter()
+                                                             .partition(|node| best.contains(&node.id));
+    flow_split(graph, left, left_cells, first_cell, cells);
+    flow_split(graph, right, cell_count - left_cells, first_cell + left_cells, cells);
+}
+
+// unit capacity max flow between the ends of the projection, treating edges as undirected;
+// returns the cut size and the nodes left on the source side
+fn min_cut<T>(graph: &Graph<T>, nodes: &[&Node<T>], direction: (f64, f64), split: usize) -> (usize, HashSet<T>)
+   where T: GraphKey {
+    let mut sorted = nodes.to_vec();
+    let projection = |node: &Node<T>| node.x * direction.0 + node.y * direction.1;
+    sorted.sort_by(|a, b| projection(a).partial_cmp(&projection(b)).unwrap());
+
+    let index: HashMap<T, usize> = sorted.iter()
+                                         .enumerate()
+                                         .map(|(i, node)| (node.id.clone(), i))
+                                         .collect();
+    let mut neighbors: Vec<HashSet<usize>> = vec![HashSet::new(); sorted.len()];
+    for (i, node) in sorted.iter().enumerate() {
+        for edge in graph.get_edges(&node.id) {
+            if let Some(&j) = index.get(&edge.to_id) {
+                if i != j {
+                    neighbors[i].insert(j);
+                    neighbors[j].insert(i);
+                }
+            }
+        }
+    }
+
+    let sources = ((split as f64 * 2.0 * TERMINAL_FRACTION) as usize).max(1).min(split.max(1));
+    let sinks = (((sorted.len() - split) as f64 * 2.0 * TERMINAL_FRACTION) as usize).max(1);
+    let is_source = |i: usize| i < sources;
+    let is_sink = |i: usize| i >= sorted.len() - sinks;
+
+    let mut flow: HashMap<(usize, usize), i64> = HashMap::new();
+    let mut cut = 0;
+    loop {
+        let (reached, sink) = residual_search(&neighbors, &flow, &is_source, &is_sink);
+        match sink {
+            Some(mut current) => {
+                while let Some(&previous) = reached.get(&current).and_then(|p| p.as_ref()) {
+                    *flow.entry((previous, current)).or_insert(0) += 1;
+                    *flow.entry((current, previous)).or_insert(0) -= 1;
+                    current = previous;
+                }
+                cut += 1;
+            }
+            None => {
+                let source_side = reached.keys().map(|&i| sorted[i].id.clone()).collect();
+                return (cut, source_side)
+            }
+        }
+    }
+}
+
+// breadth first search from all sources through edges with spare capacity,
+// returning the predecessors of the reached nodes and the first sink found
+fn residual_search<S, K>(neighbors: &[HashSet<usize>],
+                         flow: &HashMap<(usize, usize), i64>,
+                         is_source: &S,
+                         is_sink: &K) -> (HashMap<usize, Option<usize>>, Option<usize>)
+   where S: Fn(usize) -> bool,
+         K: Fn(usize) -> bool {
+    let mut reached = HashMap::new();
+    let mut queue = VecDeque::new();
+    for i in (0..neighbors.len()).filter(|&i| is_source(i)) {
+        reached.insert(i, None);
+        queue.push_back(i);
+    }
+
+    while let Some(current) = queue.pop_front() {
+        for &next in &neighbors[current] {
+            let spare = 1 - flow.get(&(current, next)).cloned().unwrap_or(0);
+            if spare > 0 && !reached.contains_key(&next) {
+                reached.insert(next, Some(current));
+                if is_sink(next) {
+                    return (reached, Some(next))
+                }
+                queue.push_back(next);
+            }
+        }
+    }
+    (reached, None)
+}
+
+#[cfg(test)]
+mod test {
+    use weighted_graph::Graph;
+    use super::{ Partition, kd_tree_partition, inertial_flow_partition };
+
+    // two 3 x 3 grids joined by a single bridge between (2, 1) and (3, 1)
+    fn build_graph() -> Graph<String> {
+        let mut graph = Graph::new();
+        let id = |x: i64, y: i64| format!("{}-{}", x, y);
+        for x in 0..6 {
+            for y in 0..3 {
+                graph.add_node(id(x, y), x as f64, y as f64);
+            }
+        }
+        let connect = |graph: &mut Graph<String>, a: String, b: String| {
+            graph.add_edge(a.clone(), a.clone(), b.clone(), 1);
+            graph.add_edge(b.clone(), b, a, 1);
+        };
+        for x in 0..6 {
+            for y in 0..3 {
+                if y < 2 {
+                    connect(&mut graph, id(x, y), id(x, y + 1));
+                }
+                if x != 2 && x < 5 {
+                    connect(&mut graph, id(x, y), id(x + 1, y));
+                }
+            }
+        }
+        connect(&mut graph, id(2, 1), id(3, 1));
+        graph
+    }
+
+    fn cell_sizes(partition: &Partition<String>) -> Vec<usize> {
+        (0..partition.cell_count()).map(|cell| partition.nodes_in_cell(cell).len()).collect()
+    }
+
+    #[test]
+    fn kd_tree_balances_cells() {
+        let graph = build_graph();
+
+        let partition = kd_tree_partition(&graph, 2);
+
+        assert_eq!(partition.cell_count(), 2);
+        assert_eq!(cell_sizes(&partition), vec![9, 9]);
+        assert_eq!(partition.cell(&"0-0".to_string()), Some(0));
+        assert_eq!(partition.cell(&"5-2".to_string()), Some(1));
+
+        let partition = kd_tree_partition(&graph, 3);
+        assert_eq!(cell_sizes(&partition), vec![6, 6, 6]);
+    }
+
+    #[test]
+    fn inertial_flow_cuts_the_bridge() {
+        let graph = build_graph();
+
+        let partition = inertial_flow_partition(&graph, 2);
+
+        assert_eq!(partition.cell_count(), 2);
+        assert_eq!(cell_sizes(&partition), vec![9, 9]);
+        assert_eq!(partition.cut_edges(&graph).len(), 2);
+        assert!(partition.cut_edges(&graph).len() <= kd_tree_partition(&graph, 2).cut_edges(&graph).len());
+    }
+
+    #[test]
+    fn every_node_gets_a_cell() {
+        let graph = build_graph();
+
+        for partition in vec![kd_tree_partition(&graph, 4), inertial_flow_partition(&graph, 4)] {
+            assert_eq!(partition.cells().len(), 18);
+            assert_eq!(partition.cell_count(), 4);
+            assert!(cell_sizes(&partition).iter().all(|&size| size > 0));
+        }
+    }
+
+    #[test]
+    fn more_cells_than_nodes() {
+        let graph = build_graph();
+        let mut small = Graph::new();
+        for &(id, x) in &[("a", 0.0), ("b", 1.0), ("c", 2.0)] {
+            small.add_node(id.to_string(), x, 0.0);
+        }
+        small.add_edge("ab".to_string(), "a".to_string(), "b".to_string(), 1);
+        small.add_edge("bc".to_string(), "b".to_string(), "c".to_string(), 1);
+
+        let partition = kd_tree_partition(&small, 10);
+        assert_eq!(partition.cell_count(), 3);
+        assert_eq!(cell_sizes(&partition), vec![1, 1, 1]);
+
+        for partition in vec![kd_tree_partition(&graph, 40),
+                              inertial_flow_partition(&graph, 40),
+                              inertial_flow_partition(&graph, 12),
+                              inertial_flow_partition(&small, 10)] {
+            assert!(partition.cell_count() <= partition.cells().len());
+            assert!(cell_sizes(&partition).iter().all(|&size| size > 0));
+        }
+        assert_eq!(kd_tree_partition(&graph, 40).cell_count(), 18);
+    }
+
+    #[test]
+    fn find_boundary_nodes() {
+        let graph = build_graph();
+
+        let partition = inertial_flow_partition(&graph, 2);
+        let left = partition.cell(&"0-0".to_string()).unwrap();
+        let right = partition.cell(&"5-0".to_string()).unwrap();
+
+        assert_eq!(partition.boundary_nodes(&graph, left), vec!["2-1".to_string()]);
+        assert_eq!(partition.boundary_nodes(&graph, right), vec!["3-1".to_string()]);
+    }
+}
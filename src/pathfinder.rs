@@ -7,7 +7,7 @@ use weighted_graph::{ GraphKey, Graph, Node, Edge };
 pub type HeuristicFn<'a, T> = Box<Fn(Option<&Node<T>>, Option<&Node<T>>) -> i64 + 'a>;
 pub type EdgeIterator<'a, T> = Box<Iterator<Item=&'a Edge<T>> + 'a>;
 pub type EdgeIteratorFn<'a, T> = Box<Fn(&'a Graph<T>, &T) ->
-                                     EdgeIterator<'a, T>>;
+                                     EdgeIterator<'a, T> + 'a>;
 pub type TerminatorFn<'a, T> = Box<Fn(&CurrentBest<T>, &HashMap<T, CurrentBest<T>>) -> bool>;
 
 pub struct Pathfinder<'a, T: GraphKey + 'a> {
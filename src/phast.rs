@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use weighted_graph::GraphKey;
+use contraction::upward_search;
+use contraction_hierarchy::ContractionHierarchy;
+
+// one-to-all distances from an upward search followed by a single sweep
+// over the downward edges, highest level first
+pub struct Phast<'a, T: 'a + GraphKey> {
+    hierarchy: &'a ContractionHierarchy<T>,
+    sweep_order: Vec<T>
+}
+
+impl<'a, T: GraphKey> Phast<'a, T> {
+    pub fn new(hierarchy: &'a ContractionHierarchy<T>) -> Self {
+        let mut levels: Vec<(i64, T)> = hierarchy.nodes_in_order()
+                                                 .into_iter()
+                                                 .map(|id| (hierarchy.level(&id).unwrap_or(0), id))
+                                                 .collect();
+        levels.sort_by(|a, b| b.0.cmp(&a.0));
+        Phast { hierarchy: hierarchy,
+                sweep_order: levels.into_iter().map(|(_, id)| id).collect()
+              }
+    }
+
+    // unreachable nodes are left out
+    pub fn distances(&self, source: &T) -> HashMap<T, i64> {
+        let mut distances: HashMap<T, i64> = upward_search(self.hierarchy, source)
+                                                 .into_iter()
+                                                 .map(|(id, best)| (id, best.cost))
+                                                 .collect();
+
+        // every downward edge comes from a higher level, which has already been swept
+        for node_id in &self.sweep_order {
+            let through_higher = self.hierarchy
+                                     .downward_edges(node_id)
+                                     .iter()
+                                     .filter_map(|edge| distances.get(&edge.from_id).map(|d| d + edge.weight))
+                                     .min();
+            if let Some(cost) = through_higher {
+                if distances.get(node_id).map_or(true, |&current| cost < current) {
+                    distances.insert(node_id.clone(), cost);
+                }
+            }
+        }
+        distances
+    }
+}
+
+pub fn one_to_all<T>(hierarchy: &ContractionHierarchy<T>, source: &T) -> HashMap<T, i64>
+       where T: GraphKey {
+    Phast::new(hierarchy).distances(source)
+}
+
+#[cfg(test)]
+mod test {
+    use weighted_graph::Graph;
+    use dijkstra::shortest_path as dijkstra;
+    use contraction::preprocess_contraction;
+    use customizable_contraction::{ build_topology, customize };
+    use super::{ Phast, one_to_all };
+
+    fn build_graph(one_way: &[(&'static str, &'static str)]) -> (Vec<&'static str>, Graph<&'static str>) {
+        let mut graph = Graph::new();
+        let nodes = vec![("a", 0.0, 3.0),
+                         ("b", 0.0, 1.0),
+                         ("c", 0.0, 0.0),
+                         ("d", 1.0, 3.0),
+                         ("e", 1.0, 2.0),
+                         ("f", 1.0, 0.0),
+                         ("g", 2.0, 3.0),
+                         ("h", 2.0, 1.0),
+                         ("i", 2.0, 0.0),
+                         ("j", 3.0, 0.0)];
+        for &(id, x, y) in &nodes {
+            graph.add_node(id, x, y);
+        }
+
+        let edges = vec![("a", "b", 3),
+                         ("a", "d", 2),
+                         ("b", "c", 1),
+                         ("b", "e", 1),
+                         ("c", "f", 2),
+                         ("d", "e", 1),
+                         ("d", "g", 2),
+                         ("e", "f", 3),
+                         ("e", "h", 1),
+                         ("f", "i", 2),
+                         ("g", "h", 4),
+                         ("h", "i", 2),
+                        ];
+        for &(n1, n2, w) in &edges {
+            graph.add_edge(n1, n1, n2, w);
+            if !one_way.contains(&(n1, n2)) {
+                graph.add_edge(n2, n2, n1, w);
+            }
+        }
+        // j can be reached but never left
+        graph.add_edge("i", "i", "j", 1);
+
+        (nodes.iter().map(|&(id, _, _)| id).collect(), graph)
+    }
+
+    fn assert_matches_dijkstra(nodes: &[&'static str], graph: &Graph<&'static str>) {
+        for hierarchy in &[preprocess_contraction(graph), customize(&build_topology(graph), graph)] {
+            let phast = Phast::new(hierarchy);
+            for source in nodes {
+                let (_, results) = dijkstra(graph, source, None);
+                let distances = phast.distances(source);
+
+                assert_eq!(distances.len(), results.len());
+                for (id, best) in &results {
+                    assert_eq!(distances.get(id), Some(&best.cost));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn distances_match_dijkstra() {
+        let (nodes, graph) = build_graph(&[]);
+
+        assert_matches_dijkstra(&nodes, &graph);
+    }
+
+    #[test]
+    fn one_way_distances_match_dijkstra() {
+        let (nodes, graph) = build_graph(&[("a", "b"), ("d", "e"), ("e", "h"), ("f", "i"), ("c", "f")]);
+
+        assert_matches_dijkstra(&nodes, &graph);
+    }
+
+    #[test]
+    fn unreachable_nodes_have_no_distance() {
+        let (_, graph) = build_graph(&[]);
+        let hierarchy = preprocess_contraction(&graph);
+
+        let distances = one_to_all(&hierarchy, &"j");
+
+        assert_eq!(distances.len(), 1);
+        assert_eq!(distances.get(&"j"), Some(&0));
+        assert_eq!(one_to_all(&hierarchy, &"a").get(&"j"), Some(&7));
+    }
+}
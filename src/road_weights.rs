@@ -28,15 +28,21 @@ lazy_static! {
 pub fn road_weight<T>(from: &Node<T>, to: &Node<T>, road_type: &str) -> Option<i64>
    where T: GraphKey {
     ROAD_TYPE_SPEED.get(road_type).map(|speed|
-       ((haversine(from.x, from.y, to.x, to.y) / *speed as f64) * 3600.0) as i64
+       travel_time(from, to, *speed as f64)
     )
 }
 
+pub fn travel_time<T>(from: &Node<T>, to: &Node<T>, speed: f64) -> i64
+   where T: GraphKey {
+    ((haversine(from.x, from.y, to.x, to.y) / speed) * 3600.0) as i64
+}
+
 fn degrees_to_radians(degrees: f64) -> f64 {
     (degrees / 180.0) * f64::consts::PI
 }
 
-fn haversine(from_lng: f64, from_lat: f64, to_lng: f64, to_lat: f64) -> f64 {
+// great circle distance in km between two lng/lat points
+pub fn haversine(from_lng: f64, from_lat: f64, to_lng: f64, to_lat: f64) -> f64 {
     let lat1 = degrees_to_radians(from_lat);
     let lat2 = degrees_to_radians(to_lat);
     let dlat = degrees_to_radians(to_lat - from_lat);
@@ -51,7 +57,7 @@ fn haversine(from_lng: f64, from_lat: f64, to_lng: f64, to_lat: f64) -> f64 {
 
 #[cfg(test)]
 mod test {
-    use super::{ ROAD_TYPE_SPEED, road_weight, haversine };
+    use super::{ ROAD_TYPE_SPEED, road_weight, travel_time, haversine };
     use weighted_graph::Node;
     use test_helpers::floats_nearly_eq;
 
@@ -73,13 +79,11 @@ mod test {
     fn test_road_weight() {
         let node_1 = Node { id: "node-1".to_string(),
                             x: -71.085743,
-                            y: 42.343212,
-                            contraction_order: None
+                            y: 42.343212
                           };
         let node_2 = Node { id: "node-2".to_string(),
                             x: -71.087792,
-                            y: 42.347249,
-                            contraction_order: None
+                            y: 42.347249
                           };
 
         let motorway_weight = road_weight(&node_1, &node_2, "motorway");
@@ -92,4 +96,19 @@ mod test {
         assert_eq!(service_weight.unwrap(), 345);
         assert_eq!(not_a_road_weight, None);
     }
+
+    #[test]
+    fn test_travel_time() {
+        let node_1 = Node { id: "node-1".to_string(),
+                            x: -71.085743,
+                            y: 42.343212
+                          };
+        let node_2 = Node { id: "node-2".to_string(),
+                            x: -71.087792,
+                            y: 42.347249
+                          };
+
+        assert_eq!(travel_time(&node_1, &node_2, 110.0), 15);
+        assert_eq!(travel_time(&node_1, &node_2, 20.0), 86);
+    }
 }
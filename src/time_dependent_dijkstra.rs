@@ -0,0 +1,185 @@
+use std::collections::{ BinaryHeap, HashMap };
+
+use weighted_graph::{ GraphKey, Graph, Node };
+use pathfinder::{ CurrentBest, HeuristicFn };
+use time_dependent_weights::TimeDependentWeights;
+
+pub fn shortest_path<'a, T>(graph: &'a Graph<T>,
+                            weights: &TimeDependentWeights<T>,
+                            source: &T,
+                            destination: Option<&T>,
+                            departure: i64
+                           ) -> (i64, HashMap<T, CurrentBest<T>>)
+    where T: GraphKey {
+    let identity = |_: Option<&Node<T>>, _ :Option<&Node<T>>| 0;
+    a_star_shortest_path(graph, weights, source, destination, departure, Box::new(identity))
+}
+
+// the heuristic must be a lower bound on the travel time at any time of day
+// returned costs are travel times from the departure at the source
+pub fn a_star_shortest_path<'a, T>(graph: &'a Graph<T>,
+                                   weights: &TimeDependentWeights<T>,
+                                   source: &T,
+                                   destination: Option<&T>,
+                                   departure: i64,
+                                   heuristic: HeuristicFn<'a, T>
+                                  ) -> (i64, HashMap<T, CurrentBest<T>>)
+    where T: GraphKey {
+    let target = destination.and_then(|id| graph.get_node(id));
+    let estimate = |node_id: &T| heuristic(graph.get_node(node_id), target);
+
+    let mut min_heap = BinaryHeap::new();
+    let mut results = HashMap::new();
+
+    results.insert(source.clone(), CurrentBest { id: source.clone(),
+                                                 cost: 0,
+                                                 predecessor: None
+                                               });
+    min_heap.push(CurrentBest { id: source.clone(),
+                                cost: estimate(source),
+                                predecessor: None
+                              });
+
+    while let Some(current) = min_heap.pop() {
+        let elapsed = results.get(&current.id).map_or(i64::max_value(), |best| best.cost);
+        if current.cost > elapsed + estimate(&current.id) {
+            // stale entry, a faster arrival has already been found
+            continue;
+        }
+        if let Some(target_id) = destination {
+            if current.id == *target_id {
+                return (elapsed, results)
+            }
+        }
+
+        for edge in graph.get_edges(&current.id) {
+            let cost = elapsed + weights.travel_time(edge, departure + elapsed);
+            let node_cost = results.get(&edge.to_id)
+                                   .map_or(i64::max_value(), |node| node.cost);
+            if cost < node_cost {
+                results.insert(edge.to_id.clone(), CurrentBest { id: edge.to_id.clone(),
+                                                                 cost: cost,
+                                                                 predecessor: Some(current.id.clone())
+                                                               });
+                min_heap.push(CurrentBest { id: edge.to_id.clone(),
+                                            cost: cost + estimate(&edge.to_id),
+                                            predecessor: Some(current.id.clone())
+                                          });
+            }
+        }
+    }
+    (0, results)
+}
+
+#[cfg(test)]
+mod test {
+    use weighted_graph::{ Graph, Node };
+    use dijkstra::shortest_path as dijkstra;
+    use time_dependent_weights::{ TimeDependentWeights, TravelTimeFunction };
+    use super::{ shortest_path, a_star_shortest_path };
+
+    const EIGHT_AM: i64 = 8 * 60 * 60;
+    const MIDNIGHT: i64 = 0;
+
+    fn build_graph() -> Graph<&'static str> {
+        let mut graph = Graph::new();
+        graph.add_node("home", 0.0, 0.0);
+        graph.add_node("highway", 5.0, 1.0);
+        graph.add_node("village", 5.0, -1.0);
+        graph.add_node("work", 10.0, 0.0);
+
+        let edges = vec![("h1", "home", "highway", 300),
+                         ("h2", "highway", "work", 300),
+                         ("v1", "home", "village", 500),
+                         ("v2", "village", "work", 500)];
+
+        for (edge_id, node_id_1, node_id_2, cost) in edges {
+            graph.add_edge(edge_id, node_id_1, node_id_2, cost);
+            graph.add_edge(edge_id, node_id_2, node_id_1, cost);
+        }
+
+        graph
+    }
+
+    fn rush_hour_weights() -> TimeDependentWeights<&'static str> {
+        let rush_hour = TravelTimeFunction::new(vec![(0, 300),
+                                                     (6 * 60 * 60, 300),
+                                                     (EIGHT_AM, 1500),
+                                                     (10 * 60 * 60, 300)]).unwrap();
+        let mut weights = TimeDependentWeights::new();
+        weights.insert("home", "highway", rush_hour.clone());
+        weights.insert("highway", "work", rush_hour);
+        weights
+    }
+
+    #[test]
+    fn static_weights_match_dijkstra() {
+        let graph = build_graph();
+        let weights = TimeDependentWeights::new();
+
+        for &source in &["home", "highway", "village", "work"] {
+            let (_, expected) = dijkstra(&graph, &source, None);
+            let (_, actual) = shortest_path(&graph, &weights, &source, None, EIGHT_AM);
+
+            for (id, best) in &expected {
+                assert_eq!(actual.get(id).map(|b| b.cost), Some(best.cost));
+            }
+        }
+    }
+
+    #[test]
+    fn route_depends_on_departure_time() {
+        let graph = build_graph();
+        let weights = rush_hour_weights();
+
+        let (night_cost, night) = shortest_path(&graph, &weights, &"home", Some(&"work"), MIDNIGHT);
+        assert_eq!(night_cost, 600);
+        assert_eq!(night.get(&"work").and_then(|b| b.predecessor), Some("highway"));
+
+        let (rush_cost, rush) = shortest_path(&graph, &weights, &"home", Some(&"work"), EIGHT_AM);
+        assert_eq!(rush_cost, 1000);
+        assert_eq!(rush.get(&"work").and_then(|b| b.predecessor), Some("village"));
+    }
+
+    #[test]
+    fn later_edges_use_arrival_time() {
+        let mut graph = Graph::new();
+        graph.add_node("home", 0.0, 0.0);
+        graph.add_node("highway", 5.0, 1.0);
+        graph.add_node("work", 10.0, 0.0);
+        graph.add_edge("h1", "home", "highway", 300);
+        graph.add_edge("h2", "highway", "work", 300);
+        let weights = rush_hour_weights();
+
+        // leaving at 7:45 the first edge takes 1350s,
+        // the second is entered at 8:07:30 and takes 1425s
+        let departure = EIGHT_AM - 15 * 60;
+        let (cost, results) = shortest_path(&graph, &weights, &"home", Some(&"work"), departure);
+
+        assert_eq!(results.get(&"highway").map(|b| b.cost), Some(1350));
+        assert_eq!(cost, 1350 + 1425);
+    }
+
+    #[test]
+    fn a_star_matches_dijkstra() {
+        let graph = build_graph();
+        let weights = rush_hour_weights();
+        let straight_line = |current: Option<&Node<&'static str>>, target: Option<&Node<&'static str>>| {
+            match (current, target) {
+                (Some(c), Some(t)) => ((t.x - c.x).powi(2) + (t.y - c.y).powi(2)).sqrt() as i64 * 10,
+                _ => 0
+            }
+        };
+
+        for &departure in &[MIDNIGHT, EIGHT_AM] {
+            let (expected, _) = shortest_path(&graph, &weights, &"home", Some(&"work"), departure);
+            let (actual, _) = a_star_shortest_path(&graph,
+                                                   &weights,
+                                                   &"home",
+                                                   Some(&"work"),
+                                                   departure,
+                                                   Box::new(straight_line));
+            assert_eq!(actual, expected);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+
+use weighted_graph::{ GraphKey, Edge };
+
+pub const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TravelTimeFunction {
+    // (seconds after midnight, travel time in seconds), sorted by time
+    // the profile repeats every day
+    points: Vec<(i64, i64)>
+}
+
+impl TravelTimeFunction {
+    pub fn new(mut points: Vec<(i64, i64)>) -> Option<Self> {
+        if points.is_empty() ||
+           points.iter().any(|&(time, travel_time)|
+               time < 0 || time >= SECONDS_PER_DAY || travel_time < 0
+           ) {
+            return None
+        }
+        points.sort_by(|a, b| a.0.cmp(&b.0));
+        points.dedup_by_key(|point| point.0);
+
+        let function = TravelTimeFunction { points: points };
+        if function.is_fifo() {
+            Some(function)
+        } else {
+            None
+        }
+    }
+
+    pub fn constant(travel_time: i64) -> Self {
+        TravelTimeFunction { points: vec![(0, travel_time)] }
+    }
+
+    // leaving later must never mean arriving earlier
+    pub fn is_fifo(&self) -> bool {
+        self.segments().iter().all(|&((t1, f1), (t2, f2))| t1 + f1 <= t2 + f2)
+    }
+
+    pub fn travel_time(&self, departure: i64) -> i64 {
+        let time = departure.rem_euclid(SECONDS_PER_DAY);
+
+        self.segments()
+            .into_iter()
+            .find(|&((t1, _), (t2, _))| t1 <= time && time < t2)
+            .or_else(|| self.segments()
+                            .into_iter()
+                            .find(|&((t1, _), (t2, _))|
+                                t1 <= time + SECONDS_PER_DAY && time + SECONDS_PER_DAY < t2
+                            )
+                            .map(|((t1, f1), (t2, f2))|
+                                ((t1 - SECONDS_PER_DAY, f1), (t2 - SECONDS_PER_DAY, f2))
+                            )
+            )
+            .map(|((t1, f1), (t2, f2))|
+                f1 + ((f2 - f1) * (time - t1)).div_euclid(t2 - t1)
+            )
+            .unwrap_or(self.points[0].1)
+    }
+
+    pub fn min_travel_time(&self) -> i64 {
+        self.points.iter().map(|&(_, travel_time)| travel_time).min().unwrap_or(0)
+    }
+
+    fn segments(&self) -> Vec<((i64, i64), (i64, i64))> {
+        let mut segments: Vec<((i64, i64), (i64, i64))> = self.points
+                                                              .windows(2)
+                                                              .map(|pair| (pair[0], pair[1]))
+                                                              .collect();
+        let &(first_time, first_travel_time) = self.points.first().unwrap();
+        let &last = self.points.last().unwrap();
+        segments.push((last, (first_time + SECONDS_PER_DAY, first_travel_time)));
+        segments
+    }
+}
+
+#[derive(Debug)]
+pub struct TimeDependentWeights<T: GraphKey> {
+    functions: HashMap<(T, T), TravelTimeFunction>
+}
+
+impl<T: GraphKey> TimeDependentWeights<T> {
+    pub fn new() -> Self {
+        TimeDependentWeights { functions: HashMap::new() }
+    }
+
+    pub fn insert(&mut self, from_id: T, to_id: T, function: TravelTimeFunction) {
+        self.functions.insert((from_id, to_id), function);
+    }
+
+    pub fn get(&self, from_id: &T, to_id: &T) -> Option<&TravelTimeFunction> {
+        self.functions.get(&(from_id.clone(), to_id.clone()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.functions.len()
+    }
+
+    // edges without a profile keep their static weight
+    pub fn travel_time(&self, edge: &Edge<T>, departure: i64) -> i64 {
+        self.get(&edge.from_id, &edge.to_id)
+            .map(|function| function.travel_time(departure))
+            .unwrap_or(edge.weight)
+    }
+
+    pub fn min_travel_time(&self, edge: &Edge<T>) -> i64 {
+        self.get(&edge.from_id, &edge.to_id)
+            .map(|function| function.min_travel_time())
+            .unwrap_or(edge.weight)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use weighted_graph::Graph;
+    use super::{ TravelTimeFunction, TimeDependentWeights, SECONDS_PER_DAY };
+
+    #[test]
+    fn interpolate_between_points() {
+        let function = TravelTimeFunction::new(vec![(0, 100),
+                                                    (1000, 200),
+                                                    (2000, 100)]).unwrap();
+
+        assert_eq!(function.travel_time(0), 100);
+        assert_eq!(function.travel_time(500), 150);
+        assert_eq!(function.travel_time(1000), 200);
+        assert_eq!(function.travel_time(1500), 150);
+        assert_eq!(function.travel_time(5000), 100);
+    }
+
+    #[test]
+    fn profile_repeats_daily() {
+        let function = TravelTimeFunction::new(vec![(3600, 100),
+                                                    (SECONDS_PER_DAY - 3600, 300)]).unwrap();
+
+        assert_eq!(function.travel_time(0), 200);
+        assert_eq!(function.travel_time(SECONDS_PER_DAY), 200);
+        assert_eq!(function.travel_time(3600 + SECONDS_PER_DAY), 100);
+        assert_eq!(function.travel_time(-3600), 300);
+    }
+
+    #[test]
+    fn unsorted_points_are_sorted() {
+        let function = TravelTimeFunction::new(vec![(1000, 200),
+                                                    (0, 100)]).unwrap();
+
+        assert_eq!(function.travel_time(500), 150);
+    }
+
+    #[test]
+    fn reject_non_fifo_functions() {
+        assert!(TravelTimeFunction::new(vec![(0, 500), (100, 10)]).is_none());
+        assert!(TravelTimeFunction::new(vec![(0, 500), (100, 400)]).is_some());
+        assert!(TravelTimeFunction::new(vec![(SECONDS_PER_DAY - 10, 500), (0, 10)]).is_none());
+    }
+
+    #[test]
+    fn reject_invalid_points() {
+        assert!(TravelTimeFunction::new(vec![]).is_none());
+        assert!(TravelTimeFunction::new(vec![(-1, 10)]).is_none());
+        assert!(TravelTimeFunction::new(vec![(SECONDS_PER_DAY, 10)]).is_none());
+        assert!(TravelTimeFunction::new(vec![(0, -10)]).is_none());
+    }
+
+    #[test]
+    fn interpolation_stays_fifo() {
+        let function = TravelTimeFunction::new(vec![(0, 700),
+                                                    (300, 401),
+                                                    (900, 900)]).unwrap();
+
+        for t in 0..2000 {
+            assert!(t + function.travel_time(t) <= t + 1 + function.travel_time(t + 1));
+        }
+    }
+
+    #[test]
+    fn edges_without_functions_use_static_weight() {
+        let mut graph = Graph::new();
+        graph.add_node("a", 0.0, 0.0);
+        graph.add_node("b", 1.0, 0.0);
+        graph.add_edge("ab", "a", "b", 7);
+        graph.add_edge("ba", "b", "a", 7);
+
+        let mut weights = TimeDependentWeights::new();
+        weights.insert("a", "b", TravelTimeFunction::new(vec![(0, 5), (100, 15)]).unwrap());
+
+        let ab = &graph.get_edges(&"a")[0];
+        let ba = &graph.get_edges(&"b")[0];
+
+        assert_eq!(weights.travel_time(ab, 50), 10);
+        assert_eq!(weights.min_travel_time(ab), 5);
+        assert_eq!(weights.travel_time(ba, 50), 7);
+        assert_eq!(weights.min_travel_time(ba), 7);
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use weighted_graph::Graph;
+use road_weights::travel_time;
+use graph_from_gtfs::time_to_seconds_after_midnight;
+use time_dependent_weights::{ TimeDependentWeights, TravelTimeFunction };
+
+extern crate csv;
+
+pub type WayId = String;
+
+// way_id, time of day (HH:MM:SS), speed in km/h
+type SpeedProfileRow = (WayId, String, f64);
+
+// rows are kept as they are, speeds of zero or less included, for the weights to reject;
+// a missing file, an undecodable row or an invalid time of day is an error
+pub fn speed_profiles_from_csv(path: &str) -> csv::Result<HashMap<WayId, Vec<(i64, f64)>>> {
+    let mut reader = csv::Reader::from_file(path)?;
+    let mut profiles = HashMap::new();
+    for row in reader.decode() {
+        let (way_id, time, speed): SpeedProfileRow = row?;
+        let time = time_to_seconds_after_midnight(&time).ok_or_else(||
+            csv::Error::Decode(format!("Invalid time of day {} for way {}", time, way_id))
+        )?;
+        profiles.entry(way_id).or_insert(Vec::new()).push((time, speed));
+    }
+    Ok(profiles)
+}
+
+// edges built from OSM carry their way id, so every segment of a way
+// shares that way's speed profile; ways whose profile has a speed of zero or less
+// or is not FIFO are left out of the weights and returned alongside them
+pub fn build_time_dependent_weights(graph: &Graph<String>,
+                                    profiles: &HashMap<WayId, Vec<(i64, f64)>>
+                                   ) -> (TimeDependentWeights<String>, Vec<WayId>) {
+    let mut weights = TimeDependentWeights::new();
+    // ways with a speed of zero or less are reported even when the graph does not have them
+    let mut rejected: Vec<WayId> = profiles.iter()
+                                           .filter(|&(_, profile)| profile.iter().any(|&(_, speed)| speed <= 0.0))
+                                           .map(|(way_id, _)| way_id.clone())
+                                           .collect();
+    rejected.sort();
+    for node in graph.all_nodes() {
+        for edge in graph.get_edges(&node.id) {
+            if let (Some(profile),
+                    Some(to_node)) = (profiles.get(&edge.id),
+                                      graph.get_node(&edge.to_id)) {
+                if rejected.contains(&edge.id) {
+                    continue;
+                }
+                let points = profile.iter()
+                                    .map(|&(time, speed)| (time, travel_time(node, to_node, speed)))
+                                    .collect();
+                match TravelTimeFunction::new(points) {
+                    Some(function) => {
+                        weights.insert(edge.from_id.clone(), edge.to_id.clone(), function);
+                    }
+                    None => {
+                        if !rejected.contains(&edge.id) {
+                            rejected.push(edge.id.clone());
+                        }
+                    }
+                }
+            }
+        }
+    }
+    (weights, rejected)
+}
+
+pub fn load_time_dependent_weights(graph: &Graph<String>,
+                                   path: &str
+                                  ) -> csv::Result<(TimeDependentWeights<String>, Vec<WayId>)> {
+    speed_profiles_from_csv(path).map(|profiles| build_time_dependent_weights(graph, &profiles))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use graph_from_xml::build_graph_from_xml;
+    use road_weights::travel_time;
+    use super::{ speed_profiles_from_csv, build_time_dependent_weights, load_time_dependent_weights };
+
+    #[test]
+    fn read_speed_profiles() {
+        let profiles = speed_profiles_from_csv("data/speed_profiles_example.csv").unwrap();
+
+        assert_eq!(profiles.len(), 3);
+        assert_eq!(profiles.get("26659127"),
+                   Some(&vec![(0, 50.0), (8 * 60 * 60, 10.0), (10 * 60 * 60, 50.0)]));
+        assert_eq!(profiles.get("26659128"), Some(&vec![(0, 70.0)]));
+        // a closed way keeps its row, for the weights to reject
+        assert_eq!(profiles.get("26659130"), Some(&vec![(0, 0.0)]));
+    }
+
+    #[test]
+    fn fail_on_unreadable_profiles() {
+        assert!(speed_profiles_from_csv("data/no_such_profiles.csv").is_err());
+        assert!(speed_profiles_from_csv("data/speed_profiles_invalid.csv").is_err());
+        assert!(load_time_dependent_weights(&build_graph_from_xml("data/example.osm"),
+                                            "data/no_such_profiles.csv").is_err());
+    }
+
+    #[test]
+    fn build_weights_for_way_segments() {
+        let graph = build_graph_from_xml("data/example.osm");
+        let (weights, rejected) = load_time_dependent_weights(&graph, "data/speed_profiles_example.csv").unwrap();
+
+        // way 26659127 has two segments and way 26659128 one, in both directions;
+        // the speed of 0 on way 26659130 is reported
+        assert_eq!(weights.len(), 6);
+        assert_eq!(rejected, vec!["26659130".to_string()]);
+
+        let from = graph.get_node(&"298884289".to_string()).unwrap();
+        let to = graph.get_node(&"261728686".to_string()).unwrap();
+        let edge = graph.get_edges(&from.id)
+                        .iter()
+                        .find(|edge| edge.to_id == to.id)
+                        .unwrap();
+
+        assert_eq!(weights.travel_time(edge, 0), travel_time(from, to, 50.0));
+        assert_eq!(weights.travel_time(edge, 8 * 60 * 60), travel_time(from, to, 10.0));
+    }
+
+    #[test]
+    fn return_ways_with_non_fifo_profiles() {
+        let graph = build_graph_from_xml("data/example.osm");
+        let mut profiles = HashMap::new();
+        // rising from 1 km/h to 100 km/h a second later lets a later start arrive earlier
+        profiles.insert("26659127".to_string(), vec![(0, 1.0), (1, 100.0)]);
+        profiles.insert("26659128".to_string(), vec![(0, 70.0)]);
+
+        let (weights, rejected) = build_time_dependent_weights(&graph, &profiles);
+
+        assert_eq!(rejected, vec!["26659127".to_string()]);
+        assert_eq!(weights.len(), 2);
+
+        profiles.insert("26659128".to_string(), vec![(0, 70.0), (8 * 60 * 60, -5.0)]);
+        let (weights, rejected) = build_time_dependent_weights(&graph, &profiles);
+
+        assert_eq!(rejected, vec!["26659128".to_string(), "26659127".to_string()]);
+        assert_eq!(weights.len(), 0);
+    }
+}
@@ -0,0 +1,451 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{ self, BufRead, Write };
+use std::str::FromStr;
+
+use weighted_graph::GraphKey;
+use many_to_many::CostMatrix;
+use load_error::{ LoadError, next_line, parse };
+
+// distances between transit nodes in one row-major vector, indexed by rank
+#[derive(PartialEq, Debug)]
+pub struct DistanceTable<T: GraphKey> {
+    transit_nodes: Vec<T>,
+    ranks: HashMap<T, usize>,
+    // None where there is no path
+    distances: Vec<Option<i64>>
+}
+
+impl<T: GraphKey> DistanceTable<T> {
+    pub fn new(transit_nodes: Vec<T>, matrix: &CostMatrix<T>) -> Self {
+        let mut distances = Vec::with_capacity(transit_nodes.len() * transit_nodes.len());
+        for from in &transit_nodes {
+            for to in &transit_nodes {
+                distances.push(matrix.get(&(from.clone(), to.clone())).cloned());
+            }
+        }
+        DistanceTable::from_parts(transit_nodes, distances)
+    }
+
+    fn from_parts(transit_nodes: Vec<T>, distances: Vec<Option<i64>>) -> Self {
+        let ranks = transit_nodes.iter()
+                                 .enumerate()
+                                 .map(|(rank, id)| (id.clone(), rank))
+                                 .collect();
+        DistanceTable { transit_nodes: transit_nodes,
+                        ranks: ranks,
+                        distances: distances
+                      }
+    }
+
+    pub fn len(&self) -> usize {
+        self.transit_nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transit_nodes.is_empty()
+    }
+
+    pub fn rank(&self, node_id: &T) -> Option<usize> {
+        self.ranks.get(node_id).cloned()
+    }
+
+    pub fn transit_node(&self, rank: usize) -> Option<&T> {
+        self.transit_nodes.get(rank)
+    }
+
+    pub fn distance(&self, from_rank: usize, to_rank: usize) -> Option<i64> {
+        if from_rank < self.len() && to_rank < self.len() {
+            self.distances[from_rank * self.len() + to_rank]
+        } else {
+            None
+        }
+    }
+}
+
+// the (rank, cost) access nodes of every node, each a slice of the same flat vectors
+#[derive(PartialEq, Debug)]
+pub struct AccessNodes<T: GraphKey> {
+    index: HashMap<T, usize>,
+    offsets: Vec<usize>,
+    ranks: Vec<usize>,
+    costs: Vec<i64>
+}
+
+impl<T: GraphKey> AccessNodes<T> {
+    pub fn new() -> Self {
+        AccessNodes { index: HashMap::new(),
+                      offsets: vec![0],
+                      ranks: Vec::new(),
+                      costs: Vec::new()
+                    }
+    }
+
+    // access nodes of a node pushed twice are replaced by the later ones
+    pub fn push(&mut self, node_id: T, access_nodes: Vec<(usize, i64)>) {
+        self.index.insert(node_id, self.offsets.len() - 1);
+        for (rank, cost) in access_nodes {
+            self.ranks.push(rank);
+            self.costs.push(cost);
+        }
+        self.offsets.push(self.ranks.len());
+    }
+
+    pub fn get(&self, node_id: &T) -> Option<(&[usize], &[i64])> {
+        self.index.get(node_id).map(|&i| {
+            let (start, end) = (self.offsets[i], self.offsets[i + 1]);
+            (&self.ranks[start..end], &self.costs[start..end])
+        })
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.index.len()
+    }
+}
+
+// the non-transit nodes in the forward and backward search spaces of every node,
+// each node numbered by the position it was pushed at and every list sorted
+#[derive(PartialEq, Debug)]
+pub struct LocalityFilter<T: GraphKey> {
+    ids: Vec<T>,
+    index: HashMap<T, usize>,
+    forward_offsets: Vec<usize>,
+    forward: Vec<usize>,
+    backward_offsets: Vec<usize>,
+    backward: Vec<usize>
+}
+
+impl<T: GraphKey> LocalityFilter<T> {
+    pub fn new() -> Self {
+        LocalityFilter { ids: Vec::new(),
+                         index: HashMap::new(),
+                         forward_offsets: vec![0],
+                         forward: Vec::new(),
+                         backward_offsets: vec![0],
+                         backward: Vec::new()
+                       }
+    }
+
+    pub fn push(&mut self, node_id: T, mut forward: Vec<usize>, mut backward: Vec<usize>) {
+        forward.sort();
+        backward.sort();
+        self.index.insert(node_id.clone(), self.ids.len());
+        self.ids.push(node_id);
+        self.forward.extend(forward);
+        self.forward_offsets.push(self.forward.len());
+        self.backward.extend(backward);
+        self.backward_offsets.push(self.backward.len());
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.ids.len()
+    }
+
+    // a shortest path avoiding every transit node has to meet below them,
+    // in the search spaces of both ends; nodes never pushed count as local
+    pub fn is_local(&self, source: &T, destination: &T) -> bool {
+        match (self.index.get(source), self.index.get(destination)) {
+            (Some(&source), Some(&destination)) =>
+                intersects(self.forward_space(source), self.backward_space(destination)),
+            _ => true
+        }
+    }
+
+    fn forward_space(&self, i: usize) -> &[usize] {
+        &self.forward[self.forward_offsets[i]..self.forward_offsets[i + 1]]
+    }
+
+    fn backward_space(&self, i: usize) -> &[usize] {
+        &self.backward[self.backward_offsets[i]..self.backward_offsets[i + 1]]
+    }
+}
+
+fn intersects(a: &[usize], b: &[usize]) -> bool {
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            return true
+        } else if a[i] < b[j] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    false
+}
+
+// everything a transit node query looks up, built once and saved as text:
+// the transit nodes one per line, a row of costs per transit node ("-" without a path),
+// then a line per node of tab separated "rank:cost" access nodes, forward before backward,
+// and last a line per node of its forward and backward search spaces, tab separated
+#[derive(PartialEq, Debug)]
+pub struct TransitNodeTable<T: GraphKey> {
+    pub distances: DistanceTable<T>,
+    pub forward: AccessNodes<T>,
+    pub backward: AccessNodes<T>,
+    pub local: LocalityFilter<T>
+}
+
+impl<T: GraphKey> TransitNodeTable<T> {
+    pub fn write_to<W>(&self, writer: &mut W) -> io::Result<()>
+           where W: Write,
+                 T: Display {
+        writeln!(writer, "transit_nodes {}", self.distances.len())?;
+        for id in &self.distances.transit_nodes {
+            writeln!(writer, "{}", id)?;
+        }
+        for from in 0..self.distances.len() {
+            let row: Vec<String> = (0..self.distances.len())
+                                       .map(|to| self.distances
+                                                     .distance(from, to)
+                                                     .map_or("-".to_string(), |cost| cost.to_string()))
+                                       .collect();
+            writeln!(writer, "{}", row.join(" "))?;
+        }
+        write_access_nodes(writer, "forward", &self.forward)?;
+        write_access_nodes(writer, "backward", &self.backward)?;
+        write_locality_filter(writer, &self.local)
+    }
+
+    pub fn read_from<R>(reader: R) -> Result<Self, LoadError>
+           where R: BufRead,
+                 T: FromStr {
+        let mut lines = reader.lines();
+
+        let count = section_size(&next_line(&mut lines)?, "transit_nodes")?;
+        let mut transit_nodes = Vec::with_capacity(count);
+        for _ in 0..count {
+            transit_nodes.push(parse(&next_line(&mut lines)?)?);
+        }
+        let mut distances = Vec::with_capacity(count * count);
+        for _ in 0..count {
+            let line = next_line(&mut lines)?;
+            let row: Vec<&str> = line.split_whitespace().collect();
+            if row.len() != count {
+                return Err(LoadError::Malformed)
+            }
+            for value in row {
+                distances.push(if value == "-" { None } else { Some(parse(value)?) });
+            }
+        }
+
+        let forward = read_access_nodes(&mut lines, "forward", count)?;
+        let backward = read_access_nodes(&mut lines, "backward", count)?;
+        let local = read_locality_filter(&mut lines)?;
+        Ok(TransitNodeTable { distances: DistanceTable::from_parts(transit_nodes, distances),
+                              forward: forward,
+                              backward: backward,
+                              local: local
+                            })
+    }
+}
+
+fn write_access_nodes<T, W>(writer: &mut W, name: &str, access_nodes: &AccessNodes<T>) -> io::Result<()>
+   where T: GraphKey + Display,
+         W: Write {
+    writeln!(writer, "{} {}", name, access_nodes.node_count())?;
+    let mut ids: Vec<(&usize, &T)> = access_nodes.index.iter().map(|(id, i)| (i, id)).collect();
+    ids.sort_by_key(|&(i, _)| *i);
+    for (_, id) in ids {
+        let (ranks, costs) = access_nodes.get(id).unwrap();
+        let mut line = id.to_string();
+        for (rank, cost) in ranks.iter().zip(costs) {
+            line.push_str(&format!("\t{}:{}", rank, cost));
+        }
+        writeln!(writer, "{}", line)?;
+    }
+    Ok(())
+}
+
+// ranks outside the table and entries that are not "rank:cost" make the whole section fail
+fn read_access_nodes<T, I>(lines: &mut I, name: &str, transit_node_count: usize) -> Result<AccessNodes<T>, LoadError>
+   where T: GraphKey + FromStr,
+         I: Iterator<Item=io::Result<String>> {
+    let count = section_size(&next_line(lines)?, name)?;
+    let mut access_nodes = AccessNodes::new();
+    for _ in 0..count {
+        let line = next_line(lines)?;
+        let mut fields = line.split('\t');
+        let id = parse(fields.next().unwrap_or(""))?;
+        let mut entries = Vec::new();
+        for field in fields {
+            let parts: Vec<&str> = field.split(':').collect();
+            if parts.len() != 2 {
+                return Err(LoadError::Malformed)
+            }
+            let rank: usize = parse(parts[0])?;
+            if rank >= transit_node_count {
+                return Err(LoadError::Malformed)
+            }
+            entries.push((rank, parse(parts[1])?));
+        }
+        access_nodes.push(id, entries);
+    }
+    if access_nodes.node_count() == count { Ok(access_nodes) } else { Err(LoadError::Malformed) }
+}
+
+fn write_locality_filter<T, W>(writer: &mut W, filter: &LocalityFilter<T>) -> io::Result<()>
+   where T: GraphKey + Display,
+         W: Write {
+    writeln!(writer, "local {}", filter.node_count())?;
+    let join = |space: &[usize]| space.iter().map(|i| i.to_string()).collect::<Vec<String>>().join(" ");
+    for (i, id) in filter.ids.iter().enumerate() {
+        writeln!(writer, "{}\t{}\t{}", id, join(filter.forward_space(i)), join(filter.backward_space(i)))?;
+    }
+    Ok(())
+}
+
+fn read_locality_filter<T, I>(lines: &mut I) -> Result<LocalityFilter<T>, LoadError>
+   where T: GraphKey + FromStr,
+         I: Iterator<Item=io::Result<String>> {
+    let count = section_size(&next_line(lines)?, "local")?;
+    let numbers = |field: &str| -> Result<Vec<usize>, LoadError> {
+        field.split_whitespace()
+             .map(|i| parse(i).and_then(|i| if i < count { Ok(i) } else { Err(LoadError::Malformed) }))
+             .collect()
+    };
+    let mut filter = LocalityFilter::new();
+    for _ in 0..count {
+        let line = next_line(lines)?;
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 3 {
+            return Err(LoadError::Malformed)
+        }
+        filter.push(parse(fields[0])?, numbers(fields[1])?, numbers(fields[2])?);
+    }
+    if filter.index.len() == count { Ok(filter) } else { Err(LoadError::Malformed) }
+}
+
+// the count from a "<name> <count>" header line
+fn section_size(line: &str, name: &str) -> Result<usize, LoadError> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.len() == 2 && parts[0] == name {
+        parse(parts[1])
+    } else {
+        Err(LoadError::Malformed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::io::{ self, BufReader, Read };
+    use load_error::LoadError;
+    use super::{ DistanceTable, AccessNodes, LocalityFilter, TransitNodeTable };
+
+    fn build_table() -> TransitNodeTable<String> {
+        let ids = vec!["x".to_string(), "y".to_string()];
+        let mut matrix = HashMap::new();
+        matrix.insert((ids[0].clone(), ids[0].clone()), 0);
+        matrix.insert((ids[0].clone(), ids[1].clone()), 4);
+        matrix.insert((ids[1].clone(), ids[1].clone()), 0);
+
+        let mut forward = AccessNodes::new();
+        forward.push("a".to_string(), vec![(0, 1), (1, 3)]);
+        forward.push("b".to_string(), vec![]);
+        let mut backward = AccessNodes::new();
+        backward.push("a".to_string(), vec![(1, 2)]);
+        let mut local = LocalityFilter::new();
+        local.push("a".to_string(), vec![1, 0], vec![0]);
+        local.push("b".to_string(), vec![1], vec![]);
+
+        TransitNodeTable { distances: DistanceTable::new(ids, &matrix),
+                           forward: forward,
+                           backward: backward,
+                           local: local
+                         }
+    }
+
+    #[test]
+    fn look_up_distances_by_rank() {
+        let table = build_table();
+
+        assert_eq!(table.distances.len(), 2);
+        assert_eq!(table.distances.rank(&"y".to_string()), Some(1));
+        assert_eq!(table.distances.transit_node(0), Some(&"x".to_string()));
+        assert_eq!(table.distances.distance(0, 1), Some(4));
+        assert_eq!(table.distances.distance(1, 0), None);
+        assert_eq!(table.distances.distance(2, 0), None);
+    }
+
+    #[test]
+    fn access_nodes_share_flat_storage() {
+        let table = build_table();
+
+        let (ranks, costs) = table.forward.get(&"a".to_string()).unwrap();
+        assert_eq!(ranks, &[0, 1]);
+        assert_eq!(costs, &[1, 3]);
+        assert_eq!(table.forward.get(&"b".to_string()).map(|(ranks, _)| ranks.len()), Some(0));
+        assert_eq!(table.backward.get(&"b".to_string()), None);
+        assert_eq!(table.forward.node_count(), 2);
+    }
+
+    #[test]
+    fn local_pairs_share_search_space() {
+        let table = build_table();
+        let (a, b) = ("a".to_string(), "b".to_string());
+
+        assert!(table.local.is_local(&a, &a));
+        assert!(!table.local.is_local(&b, &a));
+        assert!(!table.local.is_local(&a, &b));
+        assert!(table.local.is_local(&a, &"c".to_string()));
+    }
+
+    fn is_malformed(result: Result<TransitNodeTable<String>, LoadError>) -> bool {
+        match result {
+            Err(LoadError::Malformed) => true,
+            _ => false
+        }
+    }
+
+    // a reader that fails once the bytes before it run out
+    struct FailingReader;
+
+    impl Read for FailingReader {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::Other, "read failed"))
+        }
+    }
+
+    #[test]
+    fn save_and_load_table() {
+        let table = build_table();
+        let mut text = Vec::new();
+
+        table.write_to(&mut text).unwrap();
+        let loaded = TransitNodeTable::read_from(&text[..]);
+
+        assert_eq!(loaded.ok(), Some(table));
+        assert!(is_malformed(TransitNodeTable::<String>::read_from(&b"transit_nodes 3\nx\n"[..])));
+    }
+
+    #[test]
+    fn report_read_errors() {
+        let mut text = Vec::new();
+        build_table().write_to(&mut text).unwrap();
+        let truncated = BufReader::new((&text[..text.len() / 2]).chain(FailingReader));
+
+        match TransitNodeTable::<String>::read_from(truncated) {
+            Err(LoadError::Io(_)) => (),
+            other => panic!("expected an io error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn reject_corrupt_tables() {
+        let mut text = Vec::new();
+        build_table().write_to(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let load = |text: String| TransitNodeTable::<String>::read_from(text.as_bytes());
+
+        assert!(load(text.clone()).is_ok());
+        // one row short and the next one long keeps the total but shifts the columns
+        assert!(is_malformed(load(text.replace("0 4\n- 0\n", "0\n4 - 0\n"))));
+        assert!(is_malformed(load(text.replace("0 4\n", "0 x\n"))));
+        assert!(is_malformed(load(text.replace("\t1:3", "\t1:3x"))));
+        assert!(is_malformed(load(text.replace("\t1:3", "\t2:3"))));
+        assert!(is_malformed(load(text.replace("\t1:3", "\t1"))));
+        assert!(is_malformed(load(text.replace("b\t1\t\n", "b\t2\t\n"))));
+        assert!(is_malformed(load(text.replace("b\t1\t\n", "b\t1\n"))));
+    }
+}
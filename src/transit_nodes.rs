@@ -1,10 +1,17 @@
-use std::collections::{ HashSet, HashMap };
+use std::collections::{ BinaryHeap, HashSet, HashMap };
 
 use weighted_graph::{ Graph, GraphKey };
 use pathfinder::CurrentBest;
-use contraction::preprocess_contraction;
-use arc_flags::shortest_path as arc_flags_shortest_path;
+use contraction::{ parallel_map,
+                   upward_search,
+                   backward_upward_search,
+                   upward_search_stopping_at,
+                   backward_upward_search_stopping_at };
+use contraction_hierarchy::ContractionHierarchy;
 use dijkstra::shortest_path as dijkstra_shortest_path;
+use many_to_many::ch_many_to_many;
+use transit_node_table::{ AccessNodes, DistanceTable, LocalityFilter, TransitNodeTable };
+use arc_flags::grid_regions;
 use contraction::shortest_path as contraction_shortest_path;
 
 pub fn shortest_path<T>(source_distances: &HashMap<T, i64>,
@@ -38,101 +45,334 @@ fn path_cost_through_transits<T>(from: &T,
                     .map(|dist| dist + inter_cost)
 }
 
-pub fn transit_nodes_contraction<T>(graph: &mut Graph<T>) -> HashSet<T>
+pub fn transit_nodes_contraction<T>(hierarchy: &ContractionHierarchy<T>) -> HashSet<T>
        where T: GraphKey {
-    let number_transit_nodes = (graph.all_nodes().len() as f64).sqrt().floor() as usize;
-    preprocess_contraction(graph);
+    let mut nodes = hierarchy.nodes_in_order();
+    let number_transit_nodes = (nodes.len() as f64).sqrt().floor() as usize;
 
-    let mut nodes = graph.all_nodes()
-                     .iter()
-                     .map(|node| (node.contraction_order.unwrap().clone(), node.id.clone()))
-                     .collect::<Vec<(i64, T)>>();
-
-    nodes.as_mut_slice()
-         .sort_by(|a, b| b.0.cmp(&a.0));
-
-    nodes.iter()
-         .map(|node| node.1.clone())
+    nodes.reverse();
+    nodes.into_iter()
          .take(number_transit_nodes)
          .collect()
 }
 
-pub fn neighboring_transit_nodes<T>(graph: &Graph<T>,
-                                    transit_nodes: &HashSet<T>,
-                                    origin: &T)
-                                   -> HashMap<T, i64>
-   where T: GraphKey {
-    let (_, results) = arc_flags_shortest_path(graph, origin, None);
-
-    results.iter()
-           .filter_map(|(node_id, _)|
-                first_transit_node(node_id, &results, transit_nodes))
-           .filter_map(|transit_node|
-                contraction_shortest_path(graph,
-                                          origin,
-                                          &transit_node
-                                         ).map(|(cost, _)| (transit_node, cost)))
-           .collect()
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TransitNodeSelection {
+    // the top sqrt(n) nodes of the contraction order
+    Contraction,
+    // cells along each side of a grid over the graph's bounding box
+    Grid(usize)
 }
 
-fn first_transit_node<T>(node_id: &T,
-                         results: &HashMap<T, CurrentBest<T>>,
-                         transit_nodes: &HashSet<T>)
-                        -> Option<T>
-   where T: GraphKey {
-    if transit_nodes.contains(node_id) {
-        first_transit_node_helper(node_id.clone(),
-                                  Some(node_id.clone()),
-                                  results,
-                                  transit_nodes).map(|id| id.clone())
-    } else {
-        first_transit_node_helper(node_id.clone(),
-                                  None,
-                                  results,
-                                  transit_nodes).map(|id| id.clone())
+pub fn select_transit_nodes<T>(graph: &Graph<T>,
+                               hierarchy: &ContractionHierarchy<T>,
+                               selection: TransitNodeSelection) -> HashSet<T>
+       where T: GraphKey {
+    match selection {
+        TransitNodeSelection::Contraction => transit_nodes_contraction(hierarchy),
+        TransitNodeSelection::Grid(cells) => transit_nodes_grid(graph, cells)
     }
 }
 
-fn first_transit_node_helper<T>(node_id: T,
-                                current_transit_node: Option<T>,
-                                results: &HashMap<T, CurrentBest<T>>,
-                                transit_nodes: &HashSet<T>)
-                               -> Option<T>
-   where T: GraphKey {
-    match results.get(&node_id).and_then(|result| result.predecessor.clone()) {
-        Some(predecessor_id) => {
-            if transit_nodes.contains(&predecessor_id) {
-                 first_transit_node_helper(predecessor_id.clone(),
-                                           Some(predecessor_id),
-                                           results,
-                                           transit_nodes)
-                } else {
-                 first_transit_node_helper(predecessor_id,
-                                           current_transit_node,
-                                           results,
-                                           transit_nodes)
+// cells within this many cells of a cell make up its inner and outer squares
+const INNER_RADIUS: i64 = 2;
+const OUTER_RADIUS: i64 = 4;
+
+// for every cell, the nodes on the inner square's boundary that are crossed by
+// shortest paths from the cell to the outer square's boundary, searching only inside the outer square
+pub fn transit_nodes_grid<T>(graph: &Graph<T>, cells: usize) -> HashSet<T>
+       where T: GraphKey {
+    let cells = cells.max(1);
+    let positions: HashMap<T, (i64, i64)> = grid_regions(graph, cells, cells)
+                                                .into_iter()
+                                                .map(|(id, region)| (id, ((region % cells) as i64,
+                                                                          (region / cells) as i64)))
+                                                .collect();
+    // every node with the cells of its neighbors, grouped by its own cell
+    let mut by_cell: HashMap<(i64, i64), Vec<(T, Vec<Option<(i64, i64)>>)>> = HashMap::new();
+    for node in graph.all_nodes() {
+        if let Some(&position) = positions.get(&node.id) {
+            let neighbors = graph.get_edges(&node.id)
+                                 .iter()
+                                 .map(|edge| positions.get(&edge.to_id).cloned())
+                                 .collect();
+            by_cell.entry(position).or_insert_with(Vec::new).push((node.id.clone(), neighbors));
+        }
+    }
+    let mut transit_nodes = HashSet::new();
+
+    for column in 0..cells as i64 {
+        for row in 0..cells as i64 {
+            let within = |position: Option<(i64, i64)>, radius: i64| position.map_or(false, |(x, y)|
+                (x - column).abs() <= radius && (y - row).abs() <= radius
+            );
+            let boundary = |radius: i64| -> HashSet<T> {
+                let mut nodes = HashSet::new();
+                for x in column - radius..column + radius + 1 {
+                    for y in row - radius..row + radius + 1 {
+                        for &(ref id, ref neighbors) in by_cell.get(&(x, y)).into_iter().flat_map(|nodes| nodes) {
+                            if neighbors.iter().any(|&neighbor| !within(neighbor, radius)) {
+                                nodes.insert(id.clone());
+                            }
+                        }
+                    }
+                }
+                nodes
+            };
+            let cell_boundary = boundary(0);
+            let inner_boundary = boundary(INNER_RADIUS);
+            let outer_boundary = boundary(OUTER_RADIUS);
+            if outer_boundary.is_empty() {
+                continue;
+            }
+
+            let inside = |id: &T| within(positions.get(id).cloned(), OUTER_RADIUS);
+            for source in &cell_boundary {
+                let results = search_until_settled(graph, source, &outer_boundary, &inside);
+                for target in &outer_boundary {
+                    let mut current = results.get(target);
+                    while let Some(best) = current {
+                        if inner_boundary.contains(&best.id) {
+                            transit_nodes.insert(best.id.clone());
+                        }
+                        current = best.predecessor.as_ref().and_then(|id| results.get(id));
+                    }
                 }
+            }
         }
-        None => current_transit_node
     }
+    transit_nodes
 }
 
+// dijkstra over the nodes inside the area, stopping once every target is settled
+fn search_until_settled<T, F>(graph: &Graph<T>,
+                              source: &T,
+                              targets: &HashSet<T>,
+                              inside: &F) -> HashMap<T, CurrentBest<T>>
+   where T: GraphKey,
+         F: Fn(&T) -> bool {
+    let mut results = HashMap::new();
+    let mut settled = HashSet::new();
+    let mut min_heap = BinaryHeap::new();
+    let initial = CurrentBest { id: source.clone(), cost: 0, predecessor: None };
+    results.insert(source.clone(), initial.clone());
+    min_heap.push(initial);
+    let mut unsettled_targets = targets.len();
+
+    while let Some(current) = min_heap.pop() {
+        if !settled.insert(current.id.clone()) {
+            continue;
+        }
+        if targets.contains(&current.id) {
+            unsettled_targets -= 1;
+            if unsettled_targets == 0 {
+                break;
+            }
+        }
+        for edge in graph.get_edges(&current.id) {
+            let cost = current.cost + edge.weight;
+            if inside(&edge.to_id) &&
+               results.get(&edge.to_id).map_or(true, |best: &CurrentBest<T>| cost < best.cost) {
+                let best = CurrentBest { id: edge.to_id.clone(), cost: cost, predecessor: Some(current.id.clone()) };
+                results.insert(edge.to_id.clone(), best.clone());
+                min_heap.push(best);
+            }
+        }
+    }
+    results
+}
+
+// transit nodes met by a forward search that goes no further than them,
+// leaving out those reached more cheaply through another access node
+pub fn forward_access_nodes<T>(hierarchy: &ContractionHierarchy<T>,
+                               distances: &DistanceTable<T>,
+                               origin: &T) -> HashMap<T, i64>
+       where T: GraphKey {
+    let is_transit = |id: &T| distances.rank(id).is_some();
+    let reached = transit_costs(upward_search_stopping_at(hierarchy, origin, &is_transit), &is_transit);
+    prune_dominated(reached, |via, to| distances.rank(via).and_then(|via|
+                                           distances.rank(to).and_then(|to| distances.distance(via, to))))
+}
+
+// transit nodes the destination is reached from, by a backward search stopping at them
+pub fn backward_access_nodes<T>(hierarchy: &ContractionHierarchy<T>,
+                                distances: &DistanceTable<T>,
+                                destination: &T) -> HashMap<T, i64>
+       where T: GraphKey {
+    let is_transit = |id: &T| distances.rank(id).is_some();
+    let reached = transit_costs(backward_upward_search_stopping_at(hierarchy, destination, &is_transit),
+                                &is_transit);
+    prune_dominated(reached, |via, from| distances.rank(from).and_then(|from|
+                                             distances.rank(via).and_then(|via| distances.distance(from, via))))
+}
+
+fn transit_costs<T, F>(results: HashMap<T, CurrentBest<T>>, is_transit: &F) -> HashMap<T, i64>
+   where T: GraphKey,
+         F: Fn(&T) -> bool {
+    results.into_iter()
+           .filter(|&(ref id, _)| is_transit(id))
+           .map(|(id, best)| (id, best.cost))
+           .collect()
+}
+
+// an access node is dominated when going through a cheaper access node costs no more;
+// dominators are always cheaper, so every chain of them ends at a node that is kept
+fn prune_dominated<T, F>(access_nodes: HashMap<T, i64>, between: F) -> HashMap<T, i64>
+   where T: GraphKey,
+         F: Fn(&T, &T) -> Option<i64> {
+    access_nodes.iter()
+                .filter(|&(id, &cost)|
+                    !access_nodes.iter().any(|(via, &via_cost)|
+                        via != id && via_cost < cost &&
+                            between(via, id).map_or(false, |between| via_cost + between <= cost)
+                    )
+                )
+                .map(|(id, &cost)| (id.clone(), cost))
+                .collect()
+}
+
+// one search from every transit node; like dijkstra, pairs without a path cost 0
 pub fn pairwise_transit_node_distances<T>(graph: &Graph<T>,
                                           transit_nodes: &HashSet<T>
                                          ) -> HashMap<(T, T), i64>
    where T: GraphKey {
-    let mut pairs = vec![];
-    for from in transit_nodes {
-        for to in transit_nodes {
-            pairs.push((from.clone(), to.clone()));
-        }
+    transit_nodes.iter()
+                 .flat_map(|from| distances_from(graph, from, transit_nodes))
+                 .collect()
+}
+
+pub fn pairwise_transit_node_distances_in_parallel<T>(graph: &Graph<T>,
+                                                      transit_nodes: &HashSet<T>,
+                                                      threads: usize
+                                                     ) -> HashMap<(T, T), i64>
+   where T: GraphKey + Send + Sync {
+    let sources: Vec<T> = transit_nodes.iter().cloned().collect();
+    parallel_map(&sources, threads, |from| distances_from(graph, from, transit_nodes))
+        .into_iter()
+        .flat_map(|distances| distances)
+        .collect()
+}
+
+fn distances_from<T>(graph: &Graph<T>, from: &T, transit_nodes: &HashSet<T>) -> Vec<((T, T), i64)>
+   where T: GraphKey {
+    let (_, results) = dijkstra_shortest_path(graph, from, None);
+    transit_nodes.iter()
+                 .map(|to| ((from.clone(), to.clone()), results.get(to).map_or(0, |best| best.cost)))
+                 .collect()
+}
+
+// ranks follow the contraction order, so the same hierarchy always gives the same table
+pub fn build_transit_node_table<T>(hierarchy: &ContractionHierarchy<T>,
+                                   transit_nodes: &HashSet<T>) -> TransitNodeTable<T>
+       where T: GraphKey {
+    let ranked: Vec<T> = hierarchy.nodes_in_order()
+                                  .into_iter()
+                                  .filter(|id| transit_nodes.contains(id))
+                                  .collect();
+    let distances = DistanceTable::new(ranked.clone(), &ch_many_to_many(hierarchy, &ranked, &ranked));
+
+    let mut forward = AccessNodes::new();
+    let mut backward = AccessNodes::new();
+    let by_rank = |access: HashMap<T, i64>| -> Vec<(usize, i64)> {
+        access.into_iter()
+              .filter_map(|(id, cost)| distances.rank(&id).map(|rank| (rank, cost)))
+              .collect()
+    };
+    for node_id in hierarchy.nodes_in_order() {
+        forward.push(node_id.clone(), by_rank(forward_access_nodes(hierarchy, &distances, &node_id)));
+        backward.push(node_id.clone(), by_rank(backward_access_nodes(hierarchy, &distances, &node_id)));
+    }
+
+    // search spaces below the transit nodes, numbered in the order the nodes are pushed
+    let numbers: HashMap<T, usize> = hierarchy.nodes_in_order()
+                                              .into_iter()
+                                              .enumerate()
+                                              .map(|(i, id)| (id, i))
+                                              .collect();
+    let below = |results: HashMap<T, CurrentBest<T>>| -> Vec<usize> {
+        results.keys()
+               .filter(|id| !transit_nodes.contains(id))
+               .filter_map(|id| numbers.get(id).cloned())
+               .collect()
+    };
+    let mut local = LocalityFilter::new();
+    for node_id in hierarchy.nodes_in_order() {
+        local.push(node_id.clone(),
+                   below(upward_search(hierarchy, &node_id)),
+                   below(backward_upward_search(hierarchy, &node_id)));
+    }
+
+    TransitNodeTable { distances: distances,
+                       forward: forward,
+                       backward: backward,
+                       local: local
+                     }
+}
+
+// answers queries from the access nodes of both ends and the table of distances
+// between transit nodes, unless the pair is local
+pub struct TransitNodeRouter<'a, T: 'a + GraphKey> {
+    hierarchy: &'a ContractionHierarchy<T>,
+    transit_nodes: HashSet<T>,
+    table: TransitNodeTable<T>
+}
+
+impl<'a, T: GraphKey> TransitNodeRouter<'a, T> {
+    pub fn new(hierarchy: &'a ContractionHierarchy<T>) -> Self {
+        let transit_nodes = transit_nodes_contraction(hierarchy);
+        TransitNodeRouter::with_transit_nodes(hierarchy, transit_nodes)
+    }
+
+    pub fn with_transit_nodes(hierarchy: &'a ContractionHierarchy<T>, transit_nodes: HashSet<T>) -> Self {
+        let table = build_transit_node_table(hierarchy, &transit_nodes);
+        TransitNodeRouter::from_table(hierarchy, table)
     }
 
-    pairs.iter().map(|&(ref from, ref to)|
-            match dijkstra_shortest_path(graph, from, Some(to)) {
-                (cost, _) => ((from.clone(), to.clone()), cost)
+    // for a table built earlier from the same hierarchy, e.g. loaded at startup
+    pub fn from_table(hierarchy: &'a ContractionHierarchy<T>, table: TransitNodeTable<T>) -> Self {
+        let transit_nodes: HashSet<T> = (0..table.distances.len())
+                                            .filter_map(|rank| table.distances.transit_node(rank).cloned())
+                                            .collect();
+        TransitNodeRouter { hierarchy: hierarchy,
+                            transit_nodes: transit_nodes,
+                            table: table
+                          }
+    }
+
+    pub fn transit_nodes(&self) -> &HashSet<T> {
+        &self.transit_nodes
+    }
+
+    pub fn table(&self) -> &TransitNodeTable<T> {
+        &self.table
+    }
+
+    pub fn is_local(&self, source: &T, destination: &T) -> bool {
+        self.table.local.is_local(source, destination)
+    }
+
+    pub fn distance(&self, source: &T, destination: &T) -> Option<i64> {
+        if self.is_local(source, destination) {
+            return contraction_shortest_path(self.hierarchy, source, destination).map(|(cost, _)| cost)
+        }
+        match (self.table.forward.get(source), self.table.backward.get(destination)) {
+            (Some((source_ranks, source_costs)), Some((destination_ranks, destination_costs))) => {
+                let distances = &self.table.distances;
+                source_ranks.iter()
+                            .zip(source_costs)
+                            .flat_map(|(&from, &to_transit)|
+                                destination_ranks.iter()
+                                                 .zip(destination_costs)
+                                                 .filter_map(move |(&to, &from_transit)|
+                                                     distances.distance(from, to)
+                                                              .map(|cost| to_transit + cost + from_transit)
+                                                 )
+                            )
+                            .min()
             }
-         ).collect()
+            _ => None
+        }
+    }
 }
 
 #[cfg(test)]
@@ -141,9 +381,18 @@ mod test {
 
     use weighted_graph::{ Graph };
     use dijkstra::shortest_path as dijkstra;
-    use super::{ transit_nodes_contraction,
-                 neighboring_transit_nodes,
+    use contraction::preprocess_contraction;
+    use transit_node_table::TransitNodeTable;
+    use super::{ TransitNodeRouter,
+                 TransitNodeSelection,
+                 select_transit_nodes,
+                 build_transit_node_table,
+                 forward_access_nodes,
+                 backward_access_nodes,
+                 transit_nodes_contraction,
                  pairwise_transit_node_distances,
+                 pairwise_transit_node_distances_in_parallel,
+                 search_until_settled,
                  shortest_path
                };
 
@@ -180,8 +429,6 @@ mod test {
         for &(n1, n2, w) in &edges {
             graph.add_edge(n1, n1, n2, w);
             graph.add_edge(n2, n2, n1, w);
-            graph.get_mut_edge(&n1, &n2).map(|edge| edge.arc_flag = true);
-            graph.get_mut_edge(&n2, &n1).map(|edge| edge.arc_flag = true);
         }
 
         (nodes, edges, graph)
@@ -189,9 +436,10 @@ mod test {
 
     #[test]
     fn compute_set_of_transit_nodes() {
-        let (_, _, mut graph) = build_full_graph();
+        let (_, _, graph) = build_full_graph();
+        let hierarchy = preprocess_contraction(&graph);
 
-        let transit_nodes = transit_nodes_contraction(&mut graph);
+        let transit_nodes = transit_nodes_contraction(&hierarchy);
 
         let mut expected = HashSet::new();
         expected.insert(7);
@@ -200,27 +448,23 @@ mod test {
 
         assert_eq!(transit_nodes.len(), 3);
         assert_eq!(transit_nodes.iter()
-                                .filter_map(|tn|
-                                     graph.get_node(tn)
-                                          .map(|node| node.contraction_order.unwrap()))
+                                .filter_map(|tn| hierarchy.contraction_order(tn))
                                 .collect::<HashSet<i64>>(),
                    expected);
     }
 
     #[test]
     fn transit_node_distances_from_node() {
-        let (_, _, mut graph) = build_full_graph();
-        let transit_nodes = transit_nodes_contraction(&mut graph);
+        let (_, _, graph) = build_full_graph();
+        let hierarchy = preprocess_contraction(&graph);
+        let transit_nodes = transit_nodes_contraction(&hierarchy);
 
-        let first_contracted = graph.all_nodes()
-                                    .iter()
-                                    .min_by_key(|node| node.contraction_order)
-                                    .map(|node| node.id)
-                                    .unwrap();
+        let table = build_transit_node_table(&hierarchy, &transit_nodes);
+        let first_contracted = hierarchy.nodes_in_order()[0];
 
-        let transit_node_distances = neighboring_transit_nodes(&graph,
-                                                               &transit_nodes,
-                                                               &first_contracted);
+        let transit_node_distances = forward_access_nodes(&hierarchy,
+                                                          &table.distances,
+                                                          &first_contracted);
 
         assert!(transit_node_distances.len() > 0);
         assert!(transit_node_distances.keys().all(|node_id| transit_nodes.contains(node_id)));
@@ -234,8 +478,9 @@ mod test {
 
     #[test]
     fn inter_transit_node_distances() {
-        let (_, _, mut graph) = build_full_graph();
-        let transit_nodes = transit_nodes_contraction(&mut graph);
+        let (_, _, graph) = build_full_graph();
+        let hierarchy = preprocess_contraction(&graph);
+        let transit_nodes = transit_nodes_contraction(&hierarchy);
 
         let transit_node_distances = pairwise_transit_node_distances(&graph,
                                                                      &transit_nodes);
@@ -250,17 +495,19 @@ mod test {
 
     #[test]
     fn find_shortest_path() {
-        let (_, _, mut graph) = build_full_graph();
+        let (_, _, graph) = build_full_graph();
         let source = "c";
         let destination = "g";
 
-        let transit_nodes = transit_nodes_contraction(&mut graph);
-        let source_distances = neighboring_transit_nodes(&graph,
-                                                         &transit_nodes,
-                                                         &source);
-        let destination_distances = neighboring_transit_nodes(&graph,
-                                                              &transit_nodes,
-                                                              &destination);
+        let hierarchy = preprocess_contraction(&graph);
+        let transit_nodes = transit_nodes_contraction(&hierarchy);
+        let table = build_transit_node_table(&hierarchy, &transit_nodes);
+        let source_distances = forward_access_nodes(&hierarchy,
+                                                    &table.distances,
+                                                    &source);
+        let destination_distances = backward_access_nodes(&hierarchy,
+                                                          &table.distances,
+                                                          &destination);
         let inter_transit_node_distances = pairwise_transit_node_distances(&graph,
                                                                            &transit_nodes);
         match shortest_path(&source_distances,
@@ -274,4 +521,204 @@ mod test {
             None => assert!(false)
         }
     }
+
+    #[test]
+    fn router_matches_dijkstra() {
+        let (nodes, _, graph) = build_full_graph();
+        let hierarchy = preprocess_contraction(&graph);
+
+        let router = TransitNodeRouter::new(&hierarchy);
+
+        assert_eq!(router.transit_nodes(), &transit_nodes_contraction(&hierarchy));
+        assert!(nodes.iter().any(|&(from, _, _)| nodes.iter().any(|&(to, _, _)| !router.is_local(&from, &to))));
+        for &(from, _, _) in &nodes {
+            for &(to, _, _) in &nodes {
+                let (cost, _) = dijkstra(&graph, &from, Some(&to));
+                assert_eq!(router.distance(&from, &to), Some(cost));
+            }
+        }
+    }
+
+    #[test]
+    fn one_way_router_matches_dijkstra() {
+        let (nodes, _, mut graph) = build_full_graph();
+        // make a -> b and e -> h much longer than their reverse edges
+        graph.get_mut_edge(&"a", &"b").map(|edge| edge.weight = 9);
+        graph.get_mut_edge(&"e", &"h").map(|edge| edge.weight = 6);
+        let hierarchy = preprocess_contraction(&graph);
+
+        let router = TransitNodeRouter::new(&hierarchy);
+
+        for &(from, _, _) in &nodes {
+            for &(to, _, _) in &nodes {
+                let (cost, _) = dijkstra(&graph, &from, Some(&to));
+                assert_eq!(router.distance(&from, &to), Some(cost));
+            }
+        }
+    }
+
+    #[test]
+    fn neighbors_are_local() {
+        let (_, _, graph) = build_full_graph();
+        let hierarchy = preprocess_contraction(&graph);
+        let router = TransitNodeRouter::new(&hierarchy);
+        let lowest = hierarchy.nodes_in_order()[0];
+
+        assert!(router.is_local(&lowest, &lowest));
+        assert!(graph.get_edges(&lowest)
+                     .iter()
+                     .filter(|edge| !router.transit_nodes().contains(&edge.to_id))
+                     .all(|edge| router.is_local(&lowest, &edge.to_id)));
+        // unknown nodes are left to the contraction hierarchy query
+        assert!(router.is_local(&lowest, &"x"));
+        assert_eq!(router.distance(&lowest, &"x"), None);
+    }
+
+    fn build_owned_graph() -> (Vec<String>, Graph<String>) {
+        let (nodes, edges, _) = build_full_graph();
+        let mut graph = Graph::new();
+        for &(id, x, y) in &nodes {
+            graph.add_node(id.to_string(), x, y);
+        }
+        for &(n1, n2, w) in &edges {
+            graph.add_edge(n1.to_string(), n1.to_string(), n2.to_string(), w);
+            graph.add_edge(n2.to_string(), n2.to_string(), n1.to_string(), w);
+        }
+        (nodes.iter().map(|&(id, _, _)| id.to_string()).collect(), graph)
+    }
+
+    #[test]
+    fn router_from_saved_table_matches_dijkstra() {
+        let (nodes, graph) = build_owned_graph();
+        let hierarchy = preprocess_contraction(&graph);
+        let router = TransitNodeRouter::new(&hierarchy);
+        let mut text = Vec::new();
+
+        router.table().write_to(&mut text).unwrap();
+        let table = TransitNodeTable::read_from(&text[..]).unwrap();
+        assert_eq!(&table, router.table());
+        let loaded = TransitNodeRouter::from_table(&hierarchy, table);
+
+        for from in &nodes {
+            for to in &nodes {
+                let (cost, _) = dijkstra(&graph, from, Some(to));
+                assert_eq!(loaded.distance(from, to), Some(cost));
+            }
+        }
+    }
+
+    #[test]
+    fn access_nodes_are_not_dominated() {
+        let (nodes, _, graph) = build_full_graph();
+        let hierarchy = preprocess_contraction(&graph);
+        let transit_nodes = transit_nodes_contraction(&hierarchy);
+        let table = build_transit_node_table(&hierarchy, &transit_nodes);
+        let distance = |from: &'static str, to: &'static str| dijkstra(&graph, &from, Some(&to)).0;
+
+        for &(node, _, _) in &nodes {
+            let forward = forward_access_nodes(&hierarchy, &table.distances, &node);
+            let backward = backward_access_nodes(&hierarchy, &table.distances, &node);
+
+            assert!(!forward.is_empty() && !backward.is_empty());
+            for (&access, &cost) in &forward {
+                assert!(transit_nodes.contains(&access));
+                assert!(cost >= distance(node, access));
+                assert!(forward.iter().all(|(&via, &via_cost)|
+                    via_cost >= cost || via_cost + distance(via, access) > cost));
+            }
+            for (&access, &cost) in &backward {
+                assert!(cost >= distance(access, node));
+            }
+        }
+    }
+
+    // a size x size grid of nodes one apart, with unit weight streets
+    fn build_grid_graph(size: i64) -> (Vec<String>, Graph<String>) {
+        let mut graph = Graph::new();
+        let id = |x: i64, y: i64| format!("{}-{}", x, y);
+        let mut nodes = vec![];
+        for x in 0..size {
+            for y in 0..size {
+                graph.add_node(id(x, y), x as f64, y as f64);
+                nodes.push(id(x, y));
+            }
+        }
+        for x in 0..size {
+            for y in 0..size {
+                for &(nx, ny) in &[(x + 1, y), (x, y + 1)] {
+                    if nx < size && ny < size {
+                        graph.add_edge(id(x, y), id(x, y), id(nx, ny), 1 + (x * y) % 3);
+                        graph.add_edge(id(nx, ny), id(nx, ny), id(x, y), 1 + (x * y) % 3);
+                    }
+                }
+            }
+        }
+        (nodes, graph)
+    }
+
+    #[test]
+    fn select_transit_nodes_by_strategy() {
+        let (_, graph) = build_grid_graph(10);
+        let hierarchy = preprocess_contraction(&graph);
+
+        let by_contraction = select_transit_nodes(&graph, &hierarchy, TransitNodeSelection::Contraction);
+        let by_grid = select_transit_nodes(&graph, &hierarchy, TransitNodeSelection::Grid(10));
+
+        assert_eq!(by_contraction, transit_nodes_contraction(&hierarchy));
+        assert!(!by_grid.is_empty());
+        assert!(by_grid.len() < 100);
+        // a grid too coarse to have outer squares gives no transit nodes
+        assert!(select_transit_nodes(&graph, &hierarchy, TransitNodeSelection::Grid(2)).is_empty());
+    }
+
+    #[test]
+    fn grid_searches_stay_inside_and_stop_at_targets() {
+        let (_, _, graph) = build_full_graph();
+        let targets: HashSet<&str> = vec!["d"].into_iter().collect();
+
+        let results = search_until_settled(&graph, &"a", &targets, &|_: &&str| true);
+        assert_eq!(results.get(&"d").map(|best| best.cost), Some(2));
+        assert!(!results.contains_key(&"e") && !results.contains_key(&"g"));
+
+        let results = search_until_settled(&graph, &"a", &targets, &|id: &&str| *id != "d");
+        assert!(!results.contains_key(&"d"));
+        assert_eq!(results.get(&"g").map(|best| best.cost), Some(9));
+    }
+
+    #[test]
+    fn grid_router_matches_dijkstra() {
+        let (nodes, graph) = build_grid_graph(10);
+        let hierarchy = preprocess_contraction(&graph);
+        let transit_nodes = select_transit_nodes(&graph, &hierarchy, TransitNodeSelection::Grid(10));
+
+        let router = TransitNodeRouter::with_transit_nodes(&hierarchy, transit_nodes);
+
+        for from in &nodes {
+            let (_, results) = dijkstra(&graph, from, None);
+            for to in &nodes {
+                assert_eq!(router.distance(from, to), results.get(to).map(|best| best.cost));
+            }
+        }
+    }
+
+    #[test]
+    fn one_search_per_transit_node_matches_pairwise_dijkstra() {
+        let (_, _, mut graph) = build_full_graph();
+        graph.add_node("x", 5.0, 5.0);
+        graph.add_edge("x", "x", "a", 4);
+        let transit_nodes: HashSet<&str> = vec!["a", "e", "i", "x"].into_iter().collect();
+
+        let distances = pairwise_transit_node_distances(&graph, &transit_nodes);
+
+        assert_eq!(distances.len(), 16);
+        for &from in &transit_nodes {
+            for &to in &transit_nodes {
+                let (cost, _) = dijkstra(&graph, &from, Some(&to));
+                assert_eq!(distances.get(&(from, to)), Some(&cost));
+            }
+        }
+        for threads in 1..5 {
+            assert_eq!(pairwise_transit_node_distances_in_parallel(&graph, &transit_nodes, threads), distances);
+        }
+    }
 }
@@ -17,8 +17,7 @@ pub struct Graph<T: GraphKey> {
 pub struct Node<T: GraphKey> {
     pub id: T,
     pub x: f64,
-    pub y: f64,
-    pub contraction_order: Option<i64>
+    pub y: f64
 }
 
 #[derive(PartialEq, Debug)]
@@ -27,8 +26,7 @@ pub struct Edge<T: GraphKey> {
     pub from_id: T,
     pub to_id: T,
     pub weight: i64,
-    pub arc_flag: bool,
-    pub shortcut: Option<T>
+    pub arc_flag: bool
 }
 
 impl<T: GraphKey> Graph<T> {
@@ -42,8 +40,7 @@ impl<T: GraphKey> Graph<T> {
     pub fn add_node(&mut self, id: T, x: f64, y: f64) {
         let node = Node { id: id.clone(),
                           x: x,
-                          y: y,
-                          contraction_order: None
+                          y: y
                         };
         self.nodes.insert(id, node);
     }
@@ -85,8 +82,7 @@ impl<T: GraphKey> Graph<T> {
                             from_id: from_id.clone(),
                             to_id: to_id.clone(),
                             weight: weight,
-                            arc_flag: false,
-                            shortcut: None
+                            arc_flag: false
                           })
             } else {
                 None
@@ -105,12 +101,33 @@ impl<T: GraphKey> Graph<T> {
             edges.iter_mut().find(|edge| edge.to_id == *to_node_id)
         )
     }
+
+    // same nodes and edge ids, with every edge pointing the other way
+    pub fn reversed(&self) -> Graph<T> {
+        let mut reversed = Graph::new();
+        for node in self.nodes.values() {
+            reversed.add_node(node.id.clone(), node.x, node.y);
+        }
+        for edge in self.edges.values().flat_map(|edges| edges.iter()) {
+            reversed.add_edge(edge.id.clone(), edge.to_id.clone(), edge.from_id.clone(), edge.weight);
+        }
+        reversed
+    }
+}
+
+impl<T: GraphKey + Ord> Graph<T> {
+    // hash map order changes between runs, so anything that must be repeatable starts from these
+    pub fn sorted_node_ids(&self) -> Vec<T> {
+        let mut ids: Vec<T> = self.nodes.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
 }
 
 #[cfg(test)]
 mod test {
     use std::collections::HashSet;
-    use super::{ Graph, Edge };
+    use super::{ Graph, Edge, GraphKey };
     use test_helpers::floats_nearly_eq;
 
     #[test]
@@ -167,22 +184,19 @@ mod test {
                                       from_id: "n2",
                                       to_id: "n1",
                                       weight: 13,
-                                      arc_flag: false,
-                                      shortcut: None
+                                      arc_flag: false
                                     },
                                Edge { id: "e3",
                                       from_id: "n2",
                                       to_id: "n3",
                                       weight: 5,
-                                      arc_flag: false,
-                                      shortcut: None
+                                      arc_flag: false
                                     }]);
         assert_eq!(edges_n3, &[Edge { id: "e2",
                                       from_id: "n3",
                                       to_id: "n2",
                                       weight: 5,
-                                      arc_flag: false,
-                                      shortcut: None
+                                      arc_flag: false
                                     }]);
     }
 
@@ -237,10 +251,42 @@ mod test {
         let mut graph = Graph::new();
         graph.add_node("n", 0.0, 0.0);
 
-        assert_eq!(graph.get_node(&"n").and_then(|n| n.contraction_order), None);
+        graph.get_mut_node(&"n").map(|n| n.x = 1.0);
 
-        graph.get_mut_node(&"n").map(|n| n.contraction_order = Some(1));
+        assert!(graph.get_node(&"n").map_or(false, |n| floats_nearly_eq(n.x, 1.0)));
+    }
+
+    #[test]
+    fn reverse_edges() {
+        let mut graph = Graph::new();
+        graph.add_node("n1", 0.0, 12.0);
+        graph.add_node("n2", 5.0, 0.0);
+        graph.add_edge("e1", "n1", "n2", 13);
+
+        let reversed = graph.reversed();
+
+        assert_eq!(reversed.all_nodes().len(), 2);
+        assert_eq!(reversed.get_edges(&"n1"), &[]);
+        assert_eq!(reversed.get_edges(&"n2"), &[Edge { id: "e1",
+                                                       from_id: "n2",
+                                                       to_id: "n1",
+                                                       weight: 13,
+                                                       arc_flag: false
+                                                     }]);
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+    struct NumericId(u32);
+    impl GraphKey for NumericId {}
+
+    #[test]
+    fn sort_node_ids_by_their_own_order() {
+        let mut graph = Graph::new();
+        for &id in &[10, 9, 100, 1] {
+            graph.add_node(NumericId(id), 0.0, 0.0);
+        }
 
-        assert_eq!(graph.get_node(&"n").and_then(|n| n.contraction_order), Some(1));
+        assert_eq!(graph.sorted_node_ids(),
+                   vec![NumericId(1), NumericId(9), NumericId(10), NumericId(100)]);
     }
 }